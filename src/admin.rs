@@ -0,0 +1,59 @@
+use crate::app::AppState;
+use crate::error::{AppError, Result};
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap},
+    middleware::Next,
+    response::Response,
+};
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fmt;
+
+#[derive(Clone)]
+pub struct AdminToken {
+    digest: [u8; 32],
+}
+
+impl fmt::Debug for AdminToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AdminToken").finish_non_exhaustive()
+    }
+}
+
+impl AdminToken {
+    pub fn from_env() -> Option<Self> {
+        env::var("ADMIN_TOKEN")
+            .ok()
+            .filter(|token| !token.is_empty())
+            .map(|token| Self {
+                digest: Sha256::digest(token.as_bytes()).into(),
+            })
+    }
+
+    /// Digests are compared instead of the tokens so the time taken says nothing about how much of
+    /// the token was right.
+    pub fn allows(&self, headers: &HeaderMap) -> bool {
+        headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|token| Sha256::digest(token.trim().as_bytes())[..] == self.digest[..])
+    }
+}
+
+/// The `/admin` routes don't exist without `ADMIN_TOKEN`, and need it as a bearer token when
+/// it's set.
+pub async fn require_admin(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response> {
+    let Some(token) = &state.admin_token else {
+        return Err(AppError::NotFound);
+    };
+    if !token.allows(request.headers()) {
+        return Err(AppError::Unauthorized);
+    }
+    Ok(next.run(request).await)
+}
@@ -0,0 +1,192 @@
+use crate::{
+    app::AppState,
+    error::AppError,
+    post::SummaryPost,
+    routes::{search_with_fallback, spelling_suggestion, SearchParams},
+    services::{
+        search::{SearchCursor, SearchFacets},
+        search_query::SearchQuery,
+        suggest::Suggestions,
+    },
+};
+use axum::{
+    extract::{Query, State},
+    http::{header, HeaderValue, Method},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashSet;
+use std::env;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+#[derive(Debug, Serialize)]
+struct PageInfo {
+    current: usize,
+    per_page: usize,
+    total_pages: usize,
+    next_cursor: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct SearchResponse {
+    query: String,
+    results: Vec<SummaryPost>,
+    total: usize,
+    page: PageInfo,
+    facets: SearchFacets,
+    warnings: Vec<String>,
+    did_you_mean: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SuggestParams {
+    prefix: Option<String>,
+    limit: Option<usize>,
+    format: Option<String>,
+}
+
+const DEFAULT_SUGGESTIONS: usize = 5;
+const MAX_SUGGESTIONS: usize = 20;
+
+#[derive(Debug, Serialize)]
+struct SuggestResponse {
+    prefix: String,
+    #[serde(flatten)]
+    suggestions: Suggestions,
+}
+
+fn error_response(error: &AppError) -> Response {
+    (
+        error.status(),
+        Json(json!({ "error": error.public_message() })),
+    )
+        .into_response()
+}
+
+/// `CORS_ALLOWED_ORIGINS` is a comma separated list of origins or `*`. When it isn't set no
+/// cross-origin requests are allowed.
+pub fn cors_layer() -> CorsLayer {
+    let origins = env::var("CORS_ALLOWED_ORIGINS").unwrap_or_default();
+    let allow_origin = if origins.trim() == "*" {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(
+            origins
+                .split(',')
+                .map(str::trim)
+                .filter(|origin| !origin.is_empty())
+                .filter_map(|origin| match HeaderValue::from_str(origin) {
+                    Ok(value) => Some(value),
+                    Err(e) => {
+                        tracing::warn!("Ignoring invalid CORS origin {origin}: {e}");
+                        None
+                    }
+                }),
+        )
+    };
+
+    CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods([Method::GET])
+}
+
+pub async fn search(Query(params): Query<SearchParams>, State(state): State<AppState>) -> Response {
+    let (page, per_page, after) = match params.validate() {
+        Ok(paging) => paging,
+        Err(e) => return error_response(&e),
+    };
+    let query_str = params.q.unwrap_or_default();
+
+    let mut search_query = SearchQuery::from_raw(&query_str);
+    let results = match search_with_fallback(&state, &mut search_query, page, per_page, after).await
+    {
+        Ok((results, search_error)) => {
+            search_query.warnings.extend(search_error);
+            results
+        }
+        Err(err) => {
+            tracing::error!("Search failed: {:?}", err);
+            return error_response(&err.into());
+        }
+    };
+    let facets = match state.search_service.facets(&search_query).await {
+        Ok(facets) => facets,
+        Err(err) => {
+            tracing::error!("Search facets failed: {:?}", err);
+            return error_response(&err);
+        }
+    };
+
+    let did_you_mean = if results.total == 0 {
+        spelling_suggestion(&state, &query_str).await
+    } else {
+        None
+    };
+
+    Json(SearchResponse {
+        did_you_mean,
+        query: query_str,
+        page: PageInfo {
+            current: page,
+            per_page,
+            total_pages: results.total.div_ceil(per_page),
+            next_cursor: results.next_cursor.as_ref().map(SearchCursor::encode),
+        },
+        results: results.posts,
+        total: results.total,
+        facets,
+        warnings: search_query.warnings,
+    })
+    .into_response()
+}
+
+pub async fn suggest(
+    Query(params): Query<SuggestParams>,
+    State(state): State<AppState>,
+) -> Response {
+    let limit = params.limit.unwrap_or(DEFAULT_SUGGESTIONS);
+    if limit == 0 || limit > MAX_SUGGESTIONS {
+        return error_response(&AppError::BadRequest(format!(
+            "limit must be between 1 and {MAX_SUGGESTIONS}"
+        )));
+    }
+    let prefix = params.prefix.unwrap_or_default();
+    let suggestions = state.suggest_service.suggest(&prefix, limit);
+
+    match params.format.as_deref() {
+        None | Some("json") => Json(SuggestResponse {
+            suggestions,
+            prefix,
+        })
+        .into_response(),
+        Some("opensearch") => {
+            let mut completions: Vec<String> = suggestions
+                .titles
+                .into_iter()
+                .map(|t| t.title)
+                .chain(suggestions.terms.into_iter().map(|t| t.value))
+                .chain(
+                    suggestions
+                        .tags
+                        .into_iter()
+                        .map(|t| format!("tag:{}", t.value)),
+                )
+                .collect();
+            // Keeps the first of each, so the ranking order survives
+            let mut seen = HashSet::new();
+            completions.retain(|completion| seen.insert(completion.clone()));
+            completions.truncate(limit);
+
+            (
+                [(header::CONTENT_TYPE, "application/x-suggestions+json")],
+                json!([prefix, completions]).to_string(),
+            )
+                .into_response()
+        }
+        Some(other) => error_response(&AppError::BadRequest(format!(
+            "Unknown suggestion format {other}"
+        ))),
+    }
+}
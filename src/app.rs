@@ -1,7 +1,20 @@
+use crate::admin::AdminToken;
+use crate::config::SiteConfig;
 use crate::db::DbHandles;
+use crate::metrics::Metrics;
+use crate::preview::PreviewSigner;
+use crate::services::analytics::AnalyticsService;
+use crate::services::archive::ArchiveService;
 use crate::services::image::ImageService;
+use crate::services::page::PageService;
+use crate::services::redirect::RedirectService;
+use crate::services::series::SeriesService;
+use crate::services::suggest::SuggestService;
+use crate::services::tag::TagService;
 use anyhow::Result;
 use axum::response::{IntoResponse, Response};
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
 use rust_embed::RustEmbed;
 use std::sync::Arc;
 use tera::{Context, Tera};
@@ -12,23 +25,68 @@ pub struct AppState {
     pub post_service: crate::services::post::PostService,
     pub search_service: crate::services::search::SearchService,
     pub image_service: ImageService,
+    pub suggest_service: SuggestService,
+    pub analytics_service: AnalyticsService,
+    pub tag_service: TagService,
+    pub archive_service: ArchiveService,
+    pub series_service: SeriesService,
+    pub redirect_service: RedirectService,
+    pub page_service: PageService,
     pub tera: Tera,
     pub build_id: String,
+    pub site: SiteConfig,
+    pub preview: Option<PreviewSigner>,
+    pub admin_token: Option<AdminToken>,
+    pub metrics: Arc<Metrics>,
 }
 
 impl AppState {
-    pub fn new(db: Arc<DbHandles>) -> Self {
+    pub fn new(db: Arc<DbHandles>, analytics_pool: Option<Pool<SqliteConnectionManager>>) -> Self {
         let tera = Self::load_templates().unwrap();
+        let tag_service = TagService::new(db.clone());
         AppState {
-            post_service: crate::services::post::PostService::new(db.clone()),
-            search_service: crate::services::search::SearchService::new(db.clone()),
+            post_service: crate::services::post::PostService::new(
+                db.clone(),
+                tag_service.taxonomy(),
+            ),
+            search_service: crate::services::search::SearchService::new(
+                db.clone(),
+                tag_service.taxonomy(),
+            ),
             image_service: ImageService::new(db.clone()),
+            suggest_service: SuggestService::new(db.clone(), tag_service.taxonomy()),
+            analytics_service: AnalyticsService::new(analytics_pool),
+            tag_service,
+            archive_service: ArchiveService::new(db.clone()),
+            series_service: SeriesService::new(db.clone()),
+            redirect_service: RedirectService::new(db.clone()),
+            page_service: PageService::new(db.clone()),
             tera,
             build_id: build_id::get().to_string(),
+            site: SiteConfig::from_env(),
+            preview: PreviewSigner::from_env(),
+            admin_token: AdminToken::from_env(),
+            metrics: Arc::new(Metrics::default()),
             db,
         }
     }
 
+    /// Called at startup and after every database swap.
+    pub async fn refresh_indexes(&self) {
+        if let Err(e) = self.tag_service.rebuild().await {
+            tracing::error!("Failed to rebuild tag taxonomy: {}", e);
+        }
+        if let Err(e) = self.redirect_service.rebuild().await {
+            tracing::error!("Failed to load redirects: {}", e);
+        }
+        if let Err(e) = self.page_service.rebuild().await {
+            tracing::error!("Failed to load special pages: {}", e);
+        }
+        if let Err(e) = self.suggest_service.rebuild().await {
+            tracing::error!("Failed to rebuild suggest index: {}", e);
+        }
+    }
+
     fn load_templates() -> Result<Tera> {
         #[derive(RustEmbed)]
         #[folder = "templates/"]
@@ -53,15 +111,13 @@ impl AppState {
         Ok(tera)
     }
 
-    pub fn render(&self, template: &str, context: &Context) -> Result<Response> {
+    pub fn render(&self, template: &str, context: &Context) -> crate::error::Result<Response> {
         let mut context = context.clone();
         context.insert("build_id", &self.build_id);
-        match self.tera.render(template, &context) {
-            Ok(rendered) => Ok(axum::response::Html(rendered).into_response()),
-            Err(e) => {
-                tracing::error!("Template rendering failed: {}", e);
-                Err(anyhow::anyhow!("Template error: {}", e))
-            }
-        }
+        context.insert("site_url", &self.site.base_url);
+        context.insert("site_title", &self.site.title);
+        context.insert("nav_pages", &self.page_service.nav());
+        let rendered = self.tera.render(template, &context)?;
+        Ok(axum::response::Html(rendered).into_response())
     }
 }
@@ -0,0 +1,28 @@
+use std::env;
+
+#[derive(Debug, Clone)]
+pub struct SiteConfig {
+    /// Absolute URL of the site without a trailing slash.
+    pub base_url: String,
+    pub title: String,
+    pub description: String,
+    pub index_post_count: usize,
+}
+
+impl SiteConfig {
+    pub fn from_env() -> Self {
+        Self {
+            base_url: env::var("SITE_URL")
+                .map(|url| url.trim_end_matches('/').to_string())
+                .unwrap_or_else(|_| "https://jonathansm.com".to_string()),
+            title: env::var("SITE_TITLE").unwrap_or_else(|_| "Jonathan's Blog".to_string()),
+            description: env::var("SITE_DESCRIPTION")
+                .unwrap_or_else(|_| "Search posts on Jonathan's Blog".to_string()),
+            index_post_count: env::var("INDEX_POST_COUNT")
+                .ok()
+                .and_then(|count| count.parse().ok())
+                .filter(|&count| count > 0)
+                .unwrap_or(5),
+        }
+    }
+}
@@ -8,9 +8,11 @@ use anyhow::Result;
 use arc_swap::ArcSwap;
 use r2d2::Pool;
 use r2d2_sqlite::SqliteConnectionManager;
-use rusqlite::OpenFlags;
+use rusqlite::{Connection, OpenFlags};
 use tokio::{fs, sync::RwLock};
 
+use crate::post::ContentType;
+
 #[derive(Debug)]
 pub struct DbHandles {
     pub primary: ArcSwap<Pool<SqliteConnectionManager>>,
@@ -89,13 +91,122 @@ impl DbHandles {
     }
 }
 
+const OPTIONAL_TABLES: &[(&str, &str)] = &[
+    (
+        "tags",
+        "CREATE TEMP TABLE tags (name TEXT PRIMARY KEY, description TEXT)",
+    ),
+    (
+        "tag_aliases",
+        "CREATE TEMP TABLE tag_aliases (alias TEXT PRIMARY KEY, tag TEXT NOT NULL)",
+    ),
+    (
+        "tag_hierarchy",
+        "CREATE TEMP TABLE tag_hierarchy (tag TEXT PRIMARY KEY, parent TEXT NOT NULL)",
+    ),
+    (
+        "series",
+        "CREATE TEMP TABLE series (id TEXT PRIMARY KEY, title TEXT NOT NULL, description TEXT)",
+    ),
+    (
+        "series_posts",
+        "CREATE TEMP TABLE series_posts (series_id TEXT NOT NULL, post_id TEXT NOT NULL, position INTEGER NOT NULL, PRIMARY KEY (series_id, post_id))",
+    ),
+    (
+        "pinned_posts",
+        "CREATE TEMP TABLE pinned_posts (post_id TEXT PRIMARY KEY, position INTEGER NOT NULL DEFAULT 0, expires_at TEXT)",
+    ),
+    (
+        "post_visibility",
+        "CREATE TEMP TABLE post_visibility (post_id TEXT PRIMARY KEY, visibility TEXT NOT NULL, publish_at TEXT)",
+    ),
+    (
+        "redirects",
+        "CREATE TEMP TABLE redirects (from_path TEXT PRIMARY KEY, to_path TEXT, status INTEGER NOT NULL DEFAULT 301)",
+    ),
+    (
+        "post_slug_history",
+        "CREATE TEMP TABLE post_slug_history (old_id TEXT PRIMARY KEY, post_id TEXT NOT NULL)",
+    ),
+    (
+        "post_images",
+        "CREATE TEMP TABLE post_images (post_id TEXT NOT NULL, filename TEXT NOT NULL, position INTEGER NOT NULL DEFAULT 0, alt TEXT, caption TEXT, PRIMARY KEY (post_id, filename))",
+    ),
+    (
+        "post_replies",
+        "CREATE TEMP TABLE post_replies (post_id TEXT PRIMARY KEY, in_reply_to TEXT NOT NULL, context TEXT)",
+    ),
+    (
+        "special_pages",
+        "CREATE TEMP TABLE special_pages (post_id TEXT PRIMARY KEY, template TEXT, nav_position INTEGER)",
+    ),
+    (
+        "tombstones",
+        "CREATE TEMP TABLE tombstones (post_id TEXT PRIMARY KEY, deleted_at TEXT NOT NULL, reason TEXT)",
+    ),
+];
+
+/// The temp schema is writable even though the database itself is opened read-only.
+fn create_missing_optional_tables(conn: &mut Connection) -> rusqlite::Result<()> {
+    for (name, schema) in OPTIONAL_TABLES {
+        if !table_exists(conn, name)? {
+            conn.execute_batch(schema)?;
+        }
+    }
+    Ok(())
+}
+
+/// Rows with these would fail to load, so a database that has any is rejected before it goes
+/// live.
+pub fn unknown_content_types(pool: &Pool<SqliteConnectionManager>) -> Result<Vec<String>> {
+    let conn = pool.get()?;
+    let mut stmt = conn.prepare("SELECT DISTINCT content_type FROM posts")?;
+    let types = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(types
+        .into_iter()
+        .filter(|t| t != "special" && t.parse::<ContentType>().is_err())
+        .collect())
+}
+
 pub fn init_pool(path: &Path) -> Result<Pool<SqliteConnectionManager>> {
     let manager = SqliteConnectionManager::file(path)
-        .with_flags(OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_URI);
+        .with_flags(OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_URI)
+        .with_init(create_missing_optional_tables);
     let pool = Pool::builder().max_size(16).build(manager)?;
     Ok(pool)
 }
 
+/// The writable side database for data the site records itself. The content database stays
+/// read-only.
+pub fn init_analytics_pool(path: &Path) -> Result<Pool<SqliteConnectionManager>> {
+    let manager = SqliteConnectionManager::file(path).with_init(|conn| {
+        conn.execute_batch("PRAGMA journal_mode = WAL; PRAGMA busy_timeout = 5000;")
+    });
+    let pool = Pool::builder().max_size(4).build(manager)?;
+
+    pool.get()?.execute_batch(
+        r"
+        CREATE TABLE IF NOT EXISTS searches (
+            id INTEGER PRIMARY KEY,
+            day TEXT NOT NULL,
+            query TEXT NOT NULL,
+            result_count INTEGER NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS searches_query ON searches (query);
+        CREATE TABLE IF NOT EXISTS search_clicks (
+            id INTEGER PRIMARY KEY,
+            day TEXT NOT NULL,
+            query TEXT NOT NULL,
+            post_id TEXT NOT NULL
+        );
+        ",
+    )?;
+
+    Ok(pool)
+}
+
 pub async fn update_database_url_env(new_path: &std::path::Path) -> anyhow::Result<()> {
     const ENV_FILE: &str = ".env";
     let env_path = std::path::Path::new(ENV_FILE);
@@ -128,3 +239,11 @@ pub async fn update_database_url_env(new_path: &std::path::Path) -> anyhow::Resu
     fs::write(env_path, lines.join("\n")).await?;
     Ok(())
 }
+
+pub fn table_exists(conn: &rusqlite::Connection, name: &str) -> rusqlite::Result<bool> {
+    conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type IN ('table', 'view') AND name = ?)",
+        [name],
+        |row| row.get(0),
+    )
+}
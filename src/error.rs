@@ -0,0 +1,224 @@
+use crate::app::AppState;
+use crate::metrics::Metrics;
+use crate::post::SummaryPost;
+use crate::services::search::SearchError;
+use axum::{
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::any::Any;
+use std::fmt;
+use tera::Context;
+
+const RETRY_AFTER_SECONDS: &str = "5";
+
+const NOT_FOUND_MESSAGE: &str = "Nothing exists at this address.";
+const INTERNAL_MESSAGE: &str = "Something went wrong on our end.";
+
+#[derive(Debug)]
+pub enum AppError {
+    NotFound,
+    Unauthorized,
+    /// The message is shown to the reader.
+    BadRequest(String),
+    /// Temporary, like when every database connection is busy. The message is shown to the reader.
+    Unavailable(String),
+    Internal(anyhow::Error),
+}
+
+pub type Result<T, E = AppError> = std::result::Result<T, E>;
+
+impl AppError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::NotFound => StatusCode::NOT_FOUND,
+            AppError::Unauthorized => StatusCode::UNAUTHORIZED,
+            AppError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            AppError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn public_message(&self) -> String {
+        match self {
+            AppError::NotFound => NOT_FOUND_MESSAGE.to_string(),
+            AppError::Unauthorized => "This page needs an admin token.".to_string(),
+            AppError::BadRequest(message) | AppError::Unavailable(message) => message.clone(),
+            AppError::Internal(_) => INTERNAL_MESSAGE.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::NotFound => write!(f, "not found"),
+            AppError::Unauthorized => write!(f, "unauthorized"),
+            AppError::BadRequest(message) => write!(f, "bad request: {message}"),
+            AppError::Unavailable(message) => write!(f, "unavailable: {message}"),
+            AppError::Internal(e) => write!(f, "{e:#}"),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl From<anyhow::Error> for AppError {
+    fn from(e: anyhow::Error) -> Self {
+        match e.downcast::<r2d2::Error>() {
+            Ok(e) => e.into(),
+            Err(e) => AppError::Internal(e),
+        }
+    }
+}
+
+/// Getting a pooled connection only fails when none frees up in time.
+impl From<r2d2::Error> for AppError {
+    fn from(e: r2d2::Error) -> Self {
+        tracing::warn!("No database connection available: {}", e);
+        AppError::Unavailable("The site is busy right now. Please try again shortly.".to_string())
+    }
+}
+
+impl From<rusqlite::Error> for AppError {
+    fn from(e: rusqlite::Error) -> Self {
+        AppError::Internal(e.into())
+    }
+}
+
+impl From<tokio::task::JoinError> for AppError {
+    fn from(e: tokio::task::JoinError) -> Self {
+        AppError::Internal(e.into())
+    }
+}
+
+impl From<tera::Error> for AppError {
+    fn from(e: tera::Error) -> Self {
+        AppError::Internal(e.into())
+    }
+}
+
+impl From<SearchError> for AppError {
+    fn from(e: SearchError) -> Self {
+        match e {
+            SearchError::Syntax(message) => AppError::BadRequest(message),
+            SearchError::Database(e) => e.into(),
+        }
+    }
+}
+
+/// Filled in by [`render_error_pages`], since rendering needs the templates in `AppState`.
+#[derive(Debug, Clone)]
+struct ErrorPage {
+    message: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct SimilarPosts(pub Vec<SummaryPost>);
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        match &self {
+            AppError::Internal(e) => tracing::error!("Request failed: {:#}", e),
+            AppError::Unavailable(_) => tracing::warn!("Request failed: {}", self),
+            AppError::NotFound | AppError::Unauthorized | AppError::BadRequest(_) => {
+                tracing::debug!("Request failed: {}", self);
+            }
+        }
+
+        let status = self.status();
+        let page = ErrorPage {
+            message: self.public_message(),
+        };
+        let mut response = match status {
+            StatusCode::SERVICE_UNAVAILABLE => {
+                (status, [(header::RETRY_AFTER, RETRY_AFTER_SECONDS)]).into_response()
+            }
+            StatusCode::UNAUTHORIZED => {
+                (status, [(header::WWW_AUTHENTICATE, "Bearer")]).into_response()
+            }
+            _ => status.into_response(),
+        };
+        response.extensions_mut().insert(page);
+        response
+    }
+}
+
+/// Logs the panic inside the request's span and answers with the themed 500 page.
+pub fn panic_response(metrics: &Metrics, panic: Box<dyn Any + Send + 'static>) -> Response {
+    metrics.record_panic();
+    let message = panic
+        .downcast_ref::<String>()
+        .map(String::as_str)
+        .or_else(|| panic.downcast_ref::<&str>().copied())
+        .unwrap_or("no message");
+    AppError::Internal(anyhow::anyhow!("Handler panicked: {message}")).into_response()
+}
+
+/// Responses that already have a body of their own are left alone.
+fn bare_error_page(response: &Response) -> Option<ErrorPage> {
+    let status = response.status();
+    if !(status.is_client_error() || status.is_server_error())
+        || response.headers().contains_key(header::CONTENT_TYPE)
+    {
+        return None;
+    }
+
+    let message = match status {
+        StatusCode::NOT_FOUND => NOT_FOUND_MESSAGE,
+        status if status.is_server_error() => INTERNAL_MESSAGE,
+        status => status
+            .canonical_reason()
+            .unwrap_or("The request couldn't be served."),
+    };
+    Some(ErrorPage {
+        message: message.to_string(),
+    })
+}
+
+pub async fn render_error_pages(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let response = next.run(request).await;
+    let Some(page) = response
+        .extensions()
+        .get::<ErrorPage>()
+        .cloned()
+        .or_else(|| bare_error_page(&response))
+    else {
+        return response;
+    };
+
+    let status = response.status();
+    let template = match status {
+        StatusCode::NOT_FOUND => "404.html",
+        StatusCode::INTERNAL_SERVER_ERROR => "500.html",
+        _ => "error.html",
+    };
+    let mut context = Context::new();
+    context.insert("status", &status.as_u16());
+    context.insert(
+        "title",
+        status.canonical_reason().unwrap_or("Something went wrong"),
+    );
+    context.insert("message", &page.message);
+    if let Some(SimilarPosts(posts)) = response.extensions().get::<SimilarPosts>() {
+        context.insert("similar_posts", posts);
+    }
+    match state.render(template, &context) {
+        Ok(rendered) => {
+            let (mut parts, _) = response.into_parts();
+            let (rendered_parts, body) = rendered.into_parts();
+            parts.headers.extend(rendered_parts.headers);
+            Response::from_parts(parts, body)
+        }
+        Err(e) => {
+            tracing::error!("Failed to render error page: {}", e);
+            (status, format!("{}\n", page.message)).into_response()
+        }
+    }
+}
@@ -1,26 +1,35 @@
+mod admin;
+mod api;
 mod app;
+mod config;
 mod db;
+mod error;
+mod metrics;
 mod post;
+mod preview;
 mod routes;
 mod rss;
 mod services;
 
 use crate::app::AppState;
 use crate::routes::{
-    about, contact, get_image, main_page, post as post_detail, posts_index, search, sitemap,
-    switch_db, Static, WellKnown,
+    archive_index, archive_month, archive_year, fallback, get_image, main_page, metrics,
+    on_this_day, opensearch, photos, post as post_detail, posts_index, preview_link, random_post,
+    redirect_report, search, search_click, search_report, series, sitemap, special_page, switch_db,
+    tag, tags_index, Static, WellKnown,
 };
 use crate::rss::feed;
 use std::{env, path::PathBuf};
 
 use axum::{
     extract::{MatchedPath, Request},
+    middleware,
     routing::{get, post},
     Router,
 };
 use rusqlite::ffi::sqlite3_auto_extension;
 use sqlite_vec::sqlite3_vec_init;
-use tower_http::trace::TraceLayer;
+use tower_http::{catch_panic::CatchPanicLayer, trace::TraceLayer};
 use tracing::info_span;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
@@ -34,7 +43,11 @@ async fn main() {
     let db_path = PathBuf::from(env::var("DATABASE_URL").expect("No DATABASE_URL set"));
     let initial_pool = crate::db::init_pool(&db_path).expect("Failed to create initial DB pool");
     let db_handles = crate::db::DbHandles::new(initial_pool, db_path.clone());
-    let state = AppState::new(db_handles.clone());
+    let analytics_pool = env::var("ANALYTICS_DATABASE_URL").ok().map(|path| {
+        crate::db::init_analytics_pool(&PathBuf::from(path))
+            .expect("Failed to open analytics database")
+    });
+    let state = AppState::new(db_handles.clone(), analytics_pool);
 
     let static_files = axum_embed::ServeEmbed::<Static>::with_parameters(
         None,
@@ -61,19 +74,71 @@ async fn main() {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
+    match crate::db::unknown_content_types(&db_handles.primary.load()) {
+        Ok(types) if !types.is_empty() => tracing::error!(
+            "Posts with unknown content types {} will fail to load",
+            types.join(", ")
+        ),
+        Ok(_) => {}
+        Err(e) => tracing::error!("Failed to check content types: {}", e),
+    }
+    state.refresh_indexes().await;
+    let suggest_service = state.suggest_service.clone();
+    tokio::spawn(async move { suggest_service.rebuild_when_stale().await });
+
+    let panic_metrics = state.metrics.clone();
     let app = Router::new()
         .route("/", get(main_page))
         .route("/sitemap.xml", get(sitemap))
+        .route("/opensearch.xml", get(opensearch))
         .route("/search", get(search))
+        .route("/search/click", get(search_click))
+        .nest(
+            "/api",
+            Router::new()
+                .route("/search", get(crate::api::search))
+                .route("/search/suggest", get(crate::api::suggest))
+                .layer(crate::api::cors_layer()),
+        )
         .route("/posts", get(posts_index))
-        .route("/about", get(about))
-        .route("/contact", get(contact))
+        .route("/photos", get(photos))
+        .route("/tags", get(tags_index))
+        .route("/tag/:name", get(tag))
+        .route("/archive", get(archive_index))
+        .route("/archive/:year", get(archive_year))
+        .route("/archive/:year/:month", get(archive_month))
+        .route("/on-this-day", get(on_this_day))
+        .route("/random", get(random_post))
         .route("/post/:id", get(post_detail))
+        .route("/series/:id", get(series))
         .route("/feed", get(feed))
-        .route("/admin/switch_db/:filename", post(switch_db))
+        .nest(
+            "/admin",
+            Router::new()
+                .route("/switch_db/:filename", post(switch_db))
+                .route("/search_report", get(search_report))
+                .route("/preview/:id", get(preview_link))
+                .route("/redirects", get(redirect_report))
+                .route("/metrics", get(metrics))
+                .layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    crate::admin::require_admin,
+                )),
+        )
         .route("/images/:id", get(get_image))
+        .route("/:slug", get(special_page))
         .nest_service("/static", static_files)
         .nest_service("/.well-known", well_known)
+        .fallback(fallback)
+        // Inside the error pages and tracing so a panic still gets a themed page and is logged
+        // in the request's span
+        .layer(CatchPanicLayer::custom(move |panic| {
+            crate::error::panic_response(&panic_metrics, panic)
+        }))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            crate::error::render_error_pages,
+        ))
         .layer(
             TraceLayer::new_for_http().make_span_with(|request: &Request<_>| {
                 let matched_path = request
@@ -0,0 +1,29 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// They start from zero on every restart.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    handler_panics: AtomicU64,
+}
+
+impl Metrics {
+    pub fn record_panic(&self) {
+        self.handler_panics.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "# HELP jonathansm_handler_panics_total Requests whose handler panicked."
+        );
+        let _ = writeln!(out, "# TYPE jonathansm_handler_panics_total counter");
+        let _ = writeln!(
+            out,
+            "jonathansm_handler_panics_total {}",
+            self.handler_panics.load(Ordering::Relaxed)
+        );
+        out
+    }
+}
@@ -1,4 +1,7 @@
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ValueRef};
 use serde::Serialize;
+use std::fmt;
+use std::str::FromStr;
 
 #[derive(Debug, Clone, Serialize)]
 pub struct Commit {
@@ -13,14 +16,44 @@ pub enum ContentType {
     Post,
     Link,
     Quote,
+    Note,
+    Photo,
+    Reply,
 }
 
-impl From<String> for ContentType {
-    fn from(s: String) -> Self {
-        match s.as_str() {
-            "link" => ContentType::Link,
-            "quote" => ContentType::Quote,
-            _ => ContentType::Post,
+#[derive(Debug)]
+pub struct UnknownContentType(pub String);
+
+impl fmt::Display for UnknownContentType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown content type \"{}\"", self.0)
+    }
+}
+
+impl std::error::Error for UnknownContentType {}
+
+impl FromStr for ContentType {
+    type Err = UnknownContentType;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "post" => Ok(ContentType::Post),
+            "link" => Ok(ContentType::Link),
+            "quote" => Ok(ContentType::Quote),
+            "note" => Ok(ContentType::Note),
+            "photo" => Ok(ContentType::Photo),
+            "reply" => Ok(ContentType::Reply),
+            _ => Err(UnknownContentType(s.to_owned())),
+        }
+    }
+}
+
+/// Special pages are stored as `special` and render like posts.
+impl FromSql for ContentType {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "special" => Ok(ContentType::Post),
+            other => other.parse().map_err(|e| FromSqlError::Other(Box::new(e))),
         }
     }
 }
@@ -31,10 +64,44 @@ impl From<ContentType> for String {
             ContentType::Post => "post".into(),
             ContentType::Link => "link".into(),
             ContentType::Quote => "quote".into(),
+            ContentType::Note => "note".into(),
+            ContentType::Photo => "photo".into(),
+            ContentType::Reply => "reply".into(),
+        }
+    }
+}
+
+/// Posts without a `post_visibility` row are public.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Visibility {
+    #[default]
+    Public,
+    Unlisted,
+    Draft,
+    Scheduled,
+}
+
+impl From<String> for Visibility {
+    fn from(s: String) -> Self {
+        match s.as_str() {
+            "public" => Visibility::Public,
+            "unlisted" => Visibility::Unlisted,
+            "scheduled" => Visibility::Scheduled,
+            // Anything unrecognised stays hidden
+            _ => Visibility::Draft,
         }
     }
 }
 
+#[derive(Debug, Clone, Serialize)]
+pub struct PostImage {
+    pub src: String,
+    pub alt: String,
+    pub caption: Option<String>,
+    pub mime_type: &'static str,
+    pub length: i64,
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub struct Post {
     pub id: String,
@@ -51,6 +118,15 @@ pub struct Post {
     pub tags: Option<Vec<String>>,
     pub real_commits: Option<Vec<Commit>>,
     pub related_posts: Option<Vec<SummaryPost>>,
+    pub previous_post: Option<SummaryPost>,
+    pub next_post: Option<SummaryPost>,
+    pub pinned: bool,
+    /// Scheduled posts whose time has passed read as public.
+    pub visibility: Visibility,
+    pub publish_at: Option<String>,
+    pub images: Vec<PostImage>,
+    pub in_reply_to: Option<String>,
+    pub reply_context: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -63,3 +139,10 @@ pub struct SummaryPost {
     pub quote_author: Option<String>,
     pub date: String,
 }
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Tombstone {
+    pub post_id: String,
+    pub deleted_at: String,
+    pub reason: Option<String>,
+}
@@ -1,31 +1,72 @@
-use crate::{app::AppState, services::search_query::SearchQuery};
+use crate::{
+    app::AppState,
+    error::{AppError, Result, SimilarPosts},
+    post::{ContentType, Tombstone},
+    services::{
+        archive::ArchiveService,
+        post::PostScope,
+        redirect::RedirectTarget,
+        search::{SearchCursor, SearchError, SearchPage},
+        search_query::SearchQuery,
+    },
+};
 use axum::{
     extract::{Path, Query, State},
-    http::{header, HeaderMap, StatusCode},
-    response::{IntoResponse, Response},
+    http::{header, HeaderMap, StatusCode, Uri},
+    response::{IntoResponse, Redirect, Response},
 };
-use chrono::DateTime;
+use chrono::{DateTime, Datelike, Utc};
 use rust_embed::RustEmbed;
 use serde::Deserialize;
 use std::fmt::Write;
 use tera::Context;
 
-pub async fn main_page(state: State<AppState>) -> Response {
-    match state.post_service.get_main_posts().await {
-        Ok(posts) => {
-            let mut context = Context::new();
-            context.insert("title", "Jonathan's Blog");
-            context.insert("posts", &posts);
-            state.render("index.html", &context).unwrap()
-        }
-        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
-    }
+pub async fn main_page(state: State<AppState>) -> Result<Response> {
+    let posts = state
+        .post_service
+        .get_main_posts(state.site.index_post_count)
+        .await?;
+    let mut context = Context::new();
+    context.insert("title", "Jonathan's Blog");
+    context.insert("posts", &posts);
+    state.render("index.html", &context)
 }
 
 #[derive(Debug, Deserialize)]
 pub struct SearchParams {
-    q: Option<String>,
+    pub q: Option<String>,
     page: Option<usize>,
+    per_page: Option<usize>,
+    after: Option<String>,
+}
+
+const DEFAULT_PER_PAGE: usize = 10;
+const MAX_PER_PAGE: usize = 50;
+
+impl SearchParams {
+    pub fn validate(&self) -> Result<(usize, usize, Option<SearchCursor>)> {
+        let page = self.page.unwrap_or(1);
+        if page == 0 {
+            return Err(AppError::BadRequest("page must be at least 1".to_string()));
+        }
+
+        let per_page = self.per_page.unwrap_or(DEFAULT_PER_PAGE);
+        if per_page == 0 || per_page > MAX_PER_PAGE {
+            return Err(AppError::BadRequest(format!(
+                "per_page must be between 1 and {MAX_PER_PAGE}"
+            )));
+        }
+
+        let after =
+            match self.after.as_deref().filter(|a| !a.is_empty()) {
+                Some(raw) => Some(SearchCursor::decode(raw).ok_or_else(|| {
+                    AppError::BadRequest("after is not a valid cursor".to_string())
+                })?),
+                None => None,
+            };
+
+        Ok((page, per_page, after))
+    }
 }
 
 #[derive(Deserialize)]
@@ -33,129 +74,537 @@ pub struct Pagination {
     page: Option<usize>,
 }
 
-pub async fn posts_index(pagination: Query<Pagination>, state: State<AppState>) -> Response {
-    let page = pagination.page.unwrap_or(1);
-    match state.post_service.get_paginated_posts(page).await {
-        Ok((posts, current_page, total_pages)) => {
-            let mut context = Context::new();
-            context.insert("title", "All Posts");
-            context.insert("posts", &posts);
-            context.insert("current_page", &current_page);
-            context.insert("total_pages", &total_pages);
-            state.render("posts.html", &context).unwrap()
+impl Pagination {
+    fn validate(&self) -> Result<usize> {
+        match self.page.unwrap_or(1) {
+            0 => Err(AppError::BadRequest("page must be at least 1".to_string())),
+            page => Ok(page),
         }
-        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
     }
 }
 
-async fn handle_special_page(state: State<AppState>, page_id: &str) -> Response {
-    match state.post_service.get_special_page(page_id).await {
-        Ok(post) => {
-            let mut context = Context::new();
-            context.insert("post", &post);
-            state.render("post.html", &context).unwrap_or_else(|e| {
-                tracing::error!("Rendering error: {}", e);
-                StatusCode::INTERNAL_SERVER_ERROR.into_response()
-            })
-        }
-        Err(e) => {
-            tracing::error!("Database error: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+pub async fn posts_index(
+    pagination: Query<Pagination>,
+    state: State<AppState>,
+) -> Result<Response> {
+    let page = pagination.validate()?;
+    let (posts, current_page, total_pages) =
+        state.post_service.get_paginated_posts(page, None).await?;
+    let mut context = Context::new();
+    context.insert("title", "All Posts");
+    context.insert("posts", &posts);
+    context.insert("current_page", &current_page);
+    context.insert("total_pages", &total_pages);
+    state.render("posts.html", &context)
+}
+
+pub async fn photos(pagination: Query<Pagination>, state: State<AppState>) -> Result<Response> {
+    let page = pagination.validate()?;
+    let (posts, current_page, total_pages) = state
+        .post_service
+        .get_paginated_posts(page, Some(ContentType::Photo))
+        .await?;
+    let mut context = Context::new();
+    context.insert("posts", &posts);
+    context.insert("current_page", &current_page);
+    context.insert("total_pages", &total_pages);
+    state.render("photos.html", &context)
+}
+
+pub async fn tags_index(state: State<AppState>) -> Result<Response> {
+    let tags = state.tag_service.get_all_tags().await?;
+    let mut context = Context::new();
+    context.insert("title", "Tags");
+    context.insert("tags", &tags);
+    state.render("tags.html", &context)
+}
+
+pub async fn tag(
+    Path(name): Path<String>,
+    pagination: Query<Pagination>,
+    state: State<AppState>,
+) -> Result<Response> {
+    if name.is_empty() || name.len() > 100 {
+        return Err(AppError::BadRequest(
+            "tag must be between 1 and 100 characters".to_string(),
+        ));
+    }
+    let page = pagination.validate()?;
+
+    // Aliases and other spellings all live at the canonical tag's URL
+    let canonical = state.tag_service.canonical_name(&name);
+    if canonical != name {
+        let query = pagination
+            .page
+            .map(|page| format!("?page={page}"))
+            .unwrap_or_default();
+        let location = format!("/tag/{}{query}", encode_path_segment(&canonical));
+        return Ok(Redirect::permanent(&location).into_response());
+    }
+
+    let tag_page = state
+        .tag_service
+        .get_tag_page(&name, page)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    let mut context = Context::new();
+    context.insert("title", &format!("Posts tagged {}", tag_page.name));
+    context.insert("tag", &tag_page);
+    state.render("tag.html", &context)
+}
+
+pub async fn archive_index(state: State<AppState>) -> Result<Response> {
+    let years = state.archive_service.get_overview().await?;
+    let mut context = Context::new();
+    context.insert("title", "Archive");
+    context.insert("years", &years);
+    state.render("archive.html", &context)
+}
+
+pub async fn archive_year(Path(year): Path<String>, state: State<AppState>) -> Result<Response> {
+    let year = year.parse().map_err(|_| AppError::NotFound)?;
+    archive_period(state, year, None).await
+}
+
+pub async fn archive_month(
+    Path((year, month)): Path<(String, String)>,
+    state: State<AppState>,
+) -> Result<Response> {
+    let year = year.parse().map_err(|_| AppError::NotFound)?;
+    let month = month.parse().map_err(|_| AppError::NotFound)?;
+    archive_period(state, year, Some(month)).await
+}
+
+async fn archive_period(state: State<AppState>, year: u16, month: Option<u8>) -> Result<Response> {
+    if year > 9999 {
+        return Err(AppError::NotFound);
+    }
+    let period = match month {
+        Some(month) => {
+            let name = ArchiveService::month_name(month).ok_or(AppError::NotFound)?;
+            format!("{name} {year}")
         }
+        None => year.to_string(),
+    };
+
+    let posts = state.archive_service.get_period(year, month).await?;
+    if posts.is_empty() {
+        return Err(AppError::NotFound);
     }
+    let mut context = Context::new();
+    context.insert("title", &format!("Posts from {period}"));
+    context.insert("period", &period);
+    context.insert("year", &year);
+    context.insert("month", &month);
+    context.insert("posts", &posts);
+    state.render("archive_period.html", &context)
 }
 
-pub async fn about(state: State<AppState>) -> Response {
-    handle_special_page(state, "about").await
+pub async fn on_this_day(state: State<AppState>) -> Result<Response> {
+    let today = Utc::now().date_naive();
+    let posts = state
+        .archive_service
+        .get_on_this_day(today.year(), today.month(), today.day())
+        .await?;
+    let mut context = Context::new();
+    context.insert("title", "On This Day");
+    context.insert("today", &today.format("%-d %B").to_string());
+    context.insert("posts", &posts);
+    state.render("on_this_day.html", &context)
+}
+
+#[derive(Deserialize)]
+pub struct ScopeParams {
+    tag: Option<String>,
+    #[serde(rename = "type")]
+    content_type: Option<String>,
 }
 
-pub async fn contact(state: State<AppState>) -> Response {
-    match state.post_service.get_special_page("contact").await {
-        Ok(post) => {
-            let mut context = Context::new();
-            context.insert("post", &post);
-            state.render("post.html", &context).unwrap()
+impl ScopeParams {
+    fn validate(&self) -> Result<PostScope> {
+        let content_type = match self.content_type.as_deref().filter(|t| !t.is_empty()) {
+            Some(t) => Some(t.parse::<ContentType>().map_err(|_| {
+                AppError::BadRequest(
+                    "type must be post, link, quote, note, photo or reply".to_string(),
+                )
+            })?),
+            None => None,
+        };
+        Ok(PostScope {
+            tag: self.tag.clone().filter(|t| !t.is_empty()),
+            content_type,
+        })
+    }
+
+    fn to_query_string(&self) -> String {
+        let pairs: Vec<String> = [("tag", &self.tag), ("type", &self.content_type)]
+            .into_iter()
+            .filter_map(|(key, value)| {
+                let value = value.as_deref().filter(|v| !v.is_empty())?;
+                Some(format!("{key}={}", encode_path_segment(value)))
+            })
+            .collect();
+        if pairs.is_empty() {
+            String::new()
+        } else {
+            format!("?{}", pairs.join("&"))
         }
-        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
     }
 }
 
-pub async fn get_image(Path(id): Path<String>, state: State<AppState>) -> impl IntoResponse {
+pub async fn random_post(params: Query<ScopeParams>, state: State<AppState>) -> Result<Response> {
+    let scope = params.validate()?;
+    let id = state
+        .post_service
+        .get_random_post_id(&scope)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    Ok((
+        // Every visit should land somewhere new
+        [(header::CACHE_CONTROL, "no-store")],
+        Redirect::to(&format!("/post/{}", encode_path_segment(&id))),
+    )
+        .into_response())
+}
+
+/// The site's own routes win over a page with the same slug, and other paths fall through to
+/// the redirect rules.
+pub async fn special_page(Path(slug): Path<String>, state: State<AppState>) -> Result<Response> {
+    let Some(page) = state.page_service.get(&slug) else {
+        return redirect_or_not_found(&state, &format!("/{slug}"));
+    };
+    let post = state.post_service.get_special_page(&page.slug).await?;
+
+    let template = match page.template.as_deref() {
+        Some(template) if state.tera.get_template_names().any(|name| name == template) => template,
+        Some(template) => {
+            tracing::warn!(
+                "Special page {} wants missing template {}",
+                page.slug,
+                template
+            );
+            "post.html"
+        }
+        None => "post.html",
+    };
+    let mut context = Context::new();
+    context.insert("post", &post);
+    context.insert("page", &page);
+    state.render(template, &context)
+}
+
+pub async fn get_image(Path(id): Path<String>, state: State<AppState>) -> Result<Response> {
     if id.is_empty() || id.len() > 100 {
-        return StatusCode::BAD_REQUEST.into_response();
+        return Err(AppError::BadRequest(
+            "image name must be between 1 and 100 characters".to_string(),
+        ));
     }
 
     let filename = format!("images/{id}");
-    match state.image_service.get_image_data(&filename).await {
-        Ok(Some(data)) => {
-            let content_type = match id.split('.').next_back() {
-                Some("png") => "image/png",
-                Some("jpg" | "jpeg") => "image/jpeg",
-                Some("gif") => "image/gif",
-                Some("webp") => "image/webp",
-                Some("svg") => "image/svg+xml",
-                _ => "application/octet-stream",
-            };
+    let data = state
+        .image_service
+        .get_image_data(&filename)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    let content_type = crate::services::image::mime_type(&id);
+    Ok((StatusCode::OK, [(header::CONTENT_TYPE, content_type)], data).into_response())
+}
+
+#[derive(Deserialize)]
+pub struct PreviewParams {
+    preview: Option<String>,
+}
+
+pub async fn post(
+    Path(id): Path<String>,
+    params: Query<ScopeParams>,
+    preview_params: Query<PreviewParams>,
+    state: State<AppState>,
+) -> Result<Response> {
+    if id.is_empty() || id.len() > 100 {
+        return Err(AppError::BadRequest(
+            "post id must be between 1 and 100 characters".to_string(),
+        ));
+    }
+    let scope = params.validate()?;
+    // A bad or expired token is treated like no token, so drafts stay indistinguishable from
+    // missing posts
+    let preview = match (&state.preview, preview_params.preview.as_deref()) {
+        (Some(signer), Some(token)) => signer.verify(&id, token),
+        _ => false,
+    };
 
-            (StatusCode::OK, [(header::CONTENT_TYPE, content_type)], data).into_response()
+    let post = match state.post_service.get_post(&id, &scope, preview).await {
+        Ok(post) => post,
+        Err(AppError::NotFound) => return post_not_found(&state, &id).await,
+        Err(e) => return Err(e),
+    };
+    let mut context = Context::new();
+    context.insert("post", &post);
+    context.insert("preview", &preview);
+    context.insert("scope_query", &params.to_query_string());
+    match state.series_service.get_post_series(&post.id).await {
+        Ok(series) => context.insert("series", &series),
+        Err(e) => tracing::error!("Failed to get series for {}: {}", post.id, e),
+    }
+    let response = state.render("post.html", &context)?;
+    if preview {
+        return Ok((
+            [
+                (header::CACHE_CONTROL, "no-store"),
+                (header::HeaderName::from_static("x-robots-tag"), "noindex"),
+            ],
+            response,
+        )
+            .into_response());
+    }
+    Ok(response)
+}
+
+pub async fn fallback(uri: Uri, state: State<AppState>) -> Result<Response> {
+    redirect_or_not_found(&state, uri.path())
+}
+
+fn redirect_or_not_found(state: &AppState, path: &str) -> Result<Response> {
+    match state.redirect_service.resolve(path) {
+        Some(RedirectTarget::Moved {
+            location,
+            permanent,
+        }) => {
+            let status = if permanent {
+                StatusCode::MOVED_PERMANENTLY
+            } else {
+                StatusCode::FOUND
+            };
+            Ok((status, [(header::LOCATION, location)]).into_response())
         }
-        Ok(None) => StatusCode::NOT_FOUND.into_response(),
-        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        Some(RedirectTarget::Gone) => Ok(gone(state, None)),
+        None => Err(AppError::NotFound),
     }
 }
 
-pub async fn post(Path(id): Path<String>, state: State<AppState>) -> Response {
-    if id.is_empty() || id.len() > 100 {
-        return StatusCode::BAD_REQUEST.into_response();
+const SIMILAR_POST_SUGGESTIONS: usize = 5;
+
+async fn post_not_found(state: &AppState, id: &str) -> Result<Response> {
+    let path = format!("/post/{id}");
+    if state.redirect_service.resolve(&path).is_some() {
+        return redirect_or_not_found(state, &path);
     }
 
-    match state.post_service.get_post(&id).await {
-        Ok(post) => {
-            let mut context = Context::new();
-            context.insert("post", &post);
-            state.render("post.html", &context).unwrap_or_else(|e| {
-                tracing::error!("Rendering error: {}", e);
-                StatusCode::INTERNAL_SERVER_ERROR.into_response()
-            })
+    match state.post_service.get_tombstone(id).await {
+        Ok(Some(tombstone)) => return Ok(gone(state, Some(&tombstone))),
+        Ok(None) => {}
+        Err(e) => tracing::error!("Failed to look up tombstone for {}: {}", id, e),
+    }
+
+    let mut response = AppError::NotFound.into_response();
+    match state
+        .search_service
+        .similar_to_id(id, SIMILAR_POST_SUGGESTIONS)
+        .await
+    {
+        Ok(posts) if !posts.is_empty() => {
+            response.extensions_mut().insert(SimilarPosts(posts));
         }
+        Ok(_) => {}
+        Err(e) => tracing::warn!("Failed to find posts similar to {}: {}", id, e),
+    }
+    Ok(response)
+}
+
+fn gone(state: &AppState, tombstone: Option<&Tombstone>) -> Response {
+    let mut context = Context::new();
+    context.insert("title", "Gone");
+    context.insert("tombstone", &tombstone);
+    match state.render("gone.html", &context) {
+        Ok(page) => (StatusCode::GONE, page).into_response(),
         Err(e) => {
-            if e.to_string().contains("not found") {
-                StatusCode::NOT_FOUND.into_response()
-            } else {
-                tracing::error!("Database error: {}", e);
-                StatusCode::INTERNAL_SERVER_ERROR.into_response()
-            }
+            tracing::error!("Rendering error: {}", e);
+            StatusCode::GONE.into_response()
         }
     }
 }
 
-pub async fn search(Query(params): Query<SearchParams>, state: State<AppState>) -> Response {
+pub async fn metrics(state: State<AppState>) -> Response {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.metrics.render(),
+    )
+        .into_response()
+}
+
+pub async fn redirect_report(state: State<AppState>) -> Result<Response> {
+    let mut context = Context::new();
+    context.insert("report", &state.redirect_service.report());
+    state.render("redirect_report.html", &context)
+}
+
+pub async fn series(Path(id): Path<String>, state: State<AppState>) -> Result<Response> {
+    if id.is_empty() || id.len() > 100 {
+        return Err(AppError::BadRequest(
+            "series id must be between 1 and 100 characters".to_string(),
+        ));
+    }
+
+    let series = state
+        .series_service
+        .get_series(&id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    let mut context = Context::new();
+    context.insert("title", &series.title);
+    context.insert("series", &series);
+    state.render("series.html", &context)
+}
+
+pub async fn search(
+    Query(params): Query<SearchParams>,
+    state: State<AppState>,
+) -> Result<Response> {
+    let (page, per_page, after) = params.validate()?;
     let query_str = params.q.unwrap_or_default();
-    let page = params.page.unwrap_or(1);
-    let per_page = 10;
 
-    let search_query = SearchQuery::from_raw(&query_str);
+    let is_first_page = page == 1 && after.is_none();
+
+    let mut search_query = SearchQuery::from_raw(&query_str);
+    let (results, search_error) =
+        search_with_fallback(&state, &mut search_query, page, per_page, after).await?;
+    if is_first_page {
+        state
+            .analytics_service
+            .record_search(&query_str, results.total);
+    }
+
+    let mut context = Context::new();
+    context.insert("query", &query_str);
+    context.insert("posts", &results.posts);
+    context.insert("current_page", &page);
+
+    let total_pages = results.total.div_ceil(per_page);
+    context.insert("total_pages", &total_pages);
+    context.insert("per_page", &per_page);
+    context.insert("total_results", &results.total);
+    context.insert("warnings", &search_query.warnings);
+    context.insert("search_error", &search_error);
+    context.insert(
+        "next_cursor",
+        &results.next_cursor.as_ref().map(SearchCursor::encode),
+    );
+    if results.total == 0 {
+        context.insert(
+            "did_you_mean",
+            &spelling_suggestion(&state, &query_str).await,
+        );
+    }
+
+    state.render("search.html", &context)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ClickParams {
+    q: Option<String>,
+    id: String,
+}
+
+pub async fn search_click(
+    Query(params): Query<ClickParams>,
+    state: State<AppState>,
+) -> Result<Response> {
+    if params.id.is_empty() || params.id.len() > 100 || params.id.contains('/') {
+        return Err(AppError::BadRequest(
+            "id is not a valid post id".to_string(),
+        ));
+    }
+
+    state
+        .analytics_service
+        .record_click(params.q.as_deref().unwrap_or_default(), &params.id);
+    Ok(Redirect::to(&format!("/post/{}", encode_path_segment(&params.id))).into_response())
+}
+
+pub async fn search_report(state: State<AppState>) -> Result<Response> {
+    let report = state.analytics_service.report().await?;
+    let mut context = Context::new();
+    context.insert("report", &report);
+    state.render("search_report.html", &context)
+}
+
+pub async fn preview_link(Path(id): Path<String>, state: State<AppState>) -> Result<Response> {
+    if id.is_empty() || id.len() > 100 {
+        return Err(AppError::BadRequest(
+            "post id must be between 1 and 100 characters".to_string(),
+        ));
+    }
+    // Turned off by configuration, so there's nothing to retry
+    let Some(signer) = &state.preview else {
+        tracing::debug!("Preview link requested but PREVIEW_SECRET isn't set");
+        return Err(AppError::NotFound);
+    };
+
+    let link = format!(
+        "{}/post/{}?preview={}\n",
+        state.site.base_url,
+        encode_path_segment(&id),
+        signer.sign(&id)
+    );
+    Ok(([(header::CACHE_CONTROL, "no-store")], link).into_response())
+}
+
+pub async fn search_with_fallback(
+    state: &AppState,
+    search_query: &mut SearchQuery,
+    page: usize,
+    per_page: usize,
+    after: Option<SearchCursor>,
+) -> Result<(SearchPage, Option<String>), SearchError> {
     match state
         .search_service
-        .search(&search_query, page, per_page)
+        .search(search_query, page, per_page, after.clone())
         .await
     {
-        Ok((posts, total)) => {
-            let mut context = Context::new();
-            context.insert("query", &query_str);
-            context.insert("posts", &posts);
-            context.insert("current_page", &page);
-
-            let total_pages = total.div_ceil(per_page);
-            context.insert("total_pages", &total_pages);
-            context.insert("per_page", &per_page);
-            context.insert("total_results", &total);
+        Err(SearchError::Syntax(msg)) => {
+            tracing::debug!(
+                "Retrying {:?} as a literal search: {}",
+                search_query.text_query,
+                msg
+            );
+            let message = format!(
+                "Your search couldn't be read as written ({msg}), so its words were searched for as plain text."
+            );
+
+            search_query.text_query = search_query.literal_text();
+            if search_query.text_query.is_empty() {
+                let nothing = SearchPage {
+                    posts: vec![],
+                    total: 0,
+                    next_cursor: None,
+                };
+                return Ok((nothing, Some(message)));
+            }
 
-            state.render("search.html", &context).unwrap()
+            let results = state
+                .search_service
+                .search(search_query, page, per_page, after)
+                .await?;
+            Ok((results, Some(message)))
         }
-        Err(err) => {
-            tracing::error!("Search failed: {:?}", err);
-            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        other => other.map(|results| (results, None)),
+    }
+}
+
+pub async fn spelling_suggestion(state: &AppState, query_str: &str) -> Option<String> {
+    let corrected = state.suggest_service.correct_query(query_str)?;
+    let corrected_query = SearchQuery::from_raw(&corrected);
+    match state
+        .search_service
+        .search(&corrected_query, 1, 1, None)
+        .await
+    {
+        Ok(results) if results.total > 0 => Some(corrected),
+        Ok(_) => None,
+        Err(e) => {
+            tracing::warn!("Search for spelling suggestion failed: {:?}", e);
+            None
         }
     }
 }
@@ -163,33 +612,46 @@ pub async fn search(Query(params): Query<SearchParams>, state: State<AppState>)
 pub async fn switch_db(
     Path(filename): Path<String>,
     State(state): State<AppState>,
-) -> impl IntoResponse {
+) -> Result<Response> {
     if filename.is_empty() || filename.contains('/') || filename.len() > 200 {
-        return StatusCode::BAD_REQUEST.into_response();
+        return Err(AppError::BadRequest(
+            "filename must be a plain file name of at most 200 characters".to_string(),
+        ));
     }
 
     let new_path = std::path::PathBuf::from(format!("./{filename}"));
 
     if !new_path.exists() {
-        return StatusCode::NOT_FOUND.into_response();
+        return Err(AppError::NotFound);
     }
 
-    let pool = match crate::db::init_pool(&new_path) {
-        Ok(p) => p,
-        Err(e) => {
-            tracing::error!("Failed to init new pool: {}", e);
-            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
-        }
-    };
+    let pool = crate::db::init_pool(&new_path)
+        .map_err(|e| AppError::Internal(e.context("Failed to init new pool")))?;
+
+    let unknown = crate::db::unknown_content_types(&pool)
+        .map_err(|e| AppError::Internal(e.context("Failed to validate new database")))?;
+    if !unknown.is_empty() {
+        tracing::error!(
+            "Refusing to switch to {}: unknown content types {}",
+            filename,
+            unknown.join(", ")
+        );
+        return Ok((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("Unknown content types: {}\n", unknown.join(", ")),
+        )
+            .into_response());
+    }
 
     state.db.swap_primary(pool, new_path.clone()).await;
+    state.refresh_indexes().await;
 
     // Persist new DB path so the next server restart uses it
     if let Err(e) = crate::db::update_database_url_env(&new_path).await {
         tracing::error!("Failed to update .env file: {}", e);
     }
 
-    (StatusCode::OK, format!("Database switched to {filename}\n")).into_response()
+    Ok((StatusCode::OK, format!("Database switched to {filename}\n")).into_response())
 }
 
 #[derive(RustEmbed, Clone)]
@@ -201,27 +663,27 @@ pub struct Static;
 pub struct WellKnown;
 
 pub async fn sitemap(state: State<AppState>) -> Response {
-    const BASE_URL: &str = "https://jonathansm.com";
+    let base_url = xml_escape(&state.site.base_url);
 
     let mut entries = String::new();
 
     // Add static pages
-    let static_pages = [
-        "https://jonathansm.com/",
-        "https://jonathansm.com/about",
-        "https://jonathansm.com/contact",
-        "https://jonathansm.com/posts",
-        "https://jonathansm.com/feed",
-    ];
+    let static_pages = ["/", "/posts", "/tags", "/archive", "/photos", "/feed"];
 
-    for url in &static_pages {
-        write!(entries, "<url><loc>{url}</loc></url>").unwrap();
+    for path in &static_pages {
+        write!(entries, "<url><loc>{base_url}{path}</loc></url>").unwrap();
+    }
+
+    // Add special pages
+    for page in state.page_service.all() {
+        let slug = encode_path_segment(&page.slug);
+        write!(entries, "<url><loc>{base_url}/{slug}</loc></url>").unwrap();
     }
 
     // Add blog posts with last modified dates
     if let Ok(post_entries) = state.post_service.get_all_post_urls().await {
         for (id, date_str) in post_entries {
-            let url = format!("{BASE_URL}/post/{id}");
+            let url = format!("{base_url}/post/{id}");
             if let Ok(date) = DateTime::parse_from_rfc3339(&date_str) {
                 let w3c_date = date.to_rfc3339();
                 write!(
@@ -235,6 +697,42 @@ pub async fn sitemap(state: State<AppState>) -> Response {
         }
     }
 
+    // Add tag pages
+    if let Ok(tags) = state.tag_service.get_all_tags().await {
+        for tag in tags {
+            let name = encode_path_segment(&tag.name);
+            write!(entries, "<url><loc>{base_url}/tag/{name}</loc></url>").unwrap();
+        }
+    }
+
+    // Add series landing pages
+    if let Ok(ids) = state.series_service.get_all_series_ids().await {
+        for id in ids {
+            let id = encode_path_segment(&id);
+            write!(entries, "<url><loc>{base_url}/series/{id}</loc></url>").unwrap();
+        }
+    }
+
+    // Add archive pages
+    if let Ok(years) = state.archive_service.get_overview().await {
+        for year in years {
+            write!(
+                entries,
+                "<url><loc>{base_url}/archive/{}</loc></url>",
+                year.year
+            )
+            .unwrap();
+            for month in year.months {
+                write!(
+                    entries,
+                    "<url><loc>{base_url}/archive/{}/{}</loc></url>",
+                    year.year, month.month
+                )
+                .unwrap();
+            }
+        }
+    }
+
     let sitemap = format!(
         r#"<?xml version="1.0" encoding="UTF-8"?>
         <urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
@@ -247,3 +745,55 @@ pub async fn sitemap(state: State<AppState>) -> Response {
 
     (StatusCode::OK, headers, sitemap).into_response()
 }
+
+/// Matches Tera's `urlencode_strict`, so URLs built here match the links in templates.
+fn encode_path_segment(segment: &str) -> String {
+    segment.bytes().fold(String::new(), |mut out, b| {
+        if b.is_ascii_alphanumeric() {
+            out.push(char::from(b));
+        } else {
+            write!(out, "%{b:02X}").unwrap();
+        }
+        out
+    })
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+pub async fn opensearch(state: State<AppState>) -> Response {
+    let base_url = xml_escape(&state.site.base_url);
+    // The spec caps ShortName at 16 characters
+    let short_name = xml_escape(&state.site.title.chars().take(16).collect::<String>());
+    let description = xml_escape(&state.site.description);
+
+    let description_doc = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<OpenSearchDescription xmlns="http://a9.com/-/spec/opensearch/1.1/" xmlns:moz="http://www.mozilla.org/2006/browser/search/">
+    <ShortName>{short_name}</ShortName>
+    <Description>{description}</Description>
+    <InputEncoding>UTF-8</InputEncoding>
+    <Image width="16" height="16" type="image/x-icon">{base_url}/static/imgs/favicon.ico</Image>
+    <Url type="text/html" method="get" template="{base_url}/search?q={{searchTerms}}"/>
+    <Url type="application/json" method="get" template="{base_url}/api/search?q={{searchTerms}}"/>
+    <Url type="application/x-suggestions+json" method="get" template="{base_url}/api/search/suggest?format=opensearch&amp;prefix={{searchTerms}}"/>
+    <Url type="application/opensearchdescription+xml" rel="self" template="{base_url}/opensearch.xml"/>
+    <moz:SearchForm>{base_url}/search</moz:SearchForm>
+</OpenSearchDescription>"#
+    );
+
+    (
+        StatusCode::OK,
+        [(
+            header::CONTENT_TYPE,
+            "application/opensearchdescription+xml",
+        )],
+        description_doc,
+    )
+        .into_response()
+}
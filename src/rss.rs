@@ -1,9 +1,122 @@
-use crate::{post::ContentType, post::Post, AppState};
+use crate::{
+    error::Result, post::ContentType, post::Post, post::PostImage, post::Tombstone, AppState,
+};
 use axum::response::IntoResponse;
 use axum::{
     extract::State,
     http::{header, StatusCode},
 };
+use chrono::{DateTime, NaiveDateTime};
+
+const NOTE_TITLE_WORDS: usize = 8;
+
+/// Named references this doesn't know are left as they are.
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let character = rest.find(';').filter(|&end| end <= 10).and_then(|end| {
+            let name = &rest[1..end];
+            let character = match name {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some('\u{a0}'),
+                "lsquo" => Some('‘'),
+                "rsquo" => Some('’'),
+                "ldquo" => Some('“'),
+                "rdquo" => Some('”'),
+                "ndash" => Some('–'),
+                "mdash" => Some('—'),
+                "hellip" => Some('…'),
+                _ => name
+                    .strip_prefix("#x")
+                    .or_else(|| name.strip_prefix("#X"))
+                    .map(|hex| u32::from_str_radix(hex, 16))
+                    .or_else(|| name.strip_prefix('#').map(str::parse))
+                    .and_then(|code| char::from_u32(code.ok()?)),
+            };
+            character.map(|character| (character, end))
+        });
+        match character {
+            Some((character, end)) => {
+                decoded.push(character);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+fn first_words(html: &str, count: usize) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => {
+                in_tag = false;
+                text.push(' ');
+            }
+            c if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+
+    let text = decode_entities(&text);
+    let words: Vec<&str> = text.split_whitespace().collect();
+    if words.is_empty() {
+        return "Note".to_string();
+    }
+    let mut title = words[..words.len().min(count)].join(" ");
+    if words.len() > count {
+        title.push('…');
+    }
+    title
+}
+
+fn url_host(url: &str) -> &str {
+    url.split_once("://")
+        .and_then(|(_, rest)| rest.split(['/', '?', '#']).next())
+        .filter(|host| !host.is_empty())
+        .unwrap_or(url)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// For feed readers that ignore Media RSS.
+fn gallery_html(base_url: &str, images: &[PostImage]) -> String {
+    images
+        .iter()
+        .map(|image| {
+            let caption = image
+                .caption
+                .as_deref()
+                .map_or_else(String::new, |caption| {
+                    format!("<figcaption>{}</figcaption>", escape(caption))
+                });
+            format!(
+                r#"<figure><img src="{}" alt="{}">{caption}</figure>"#,
+                escape(&format!("{base_url}{}", image.src)),
+                escape(&image.alt)
+            )
+        })
+        .collect()
+}
 
 struct RssEntry {
     title: String,
@@ -11,11 +124,12 @@ struct RssEntry {
     content: String,
     pub_date: String,
     guid: String,
+    images: Vec<PostImage>,
 }
 
-impl From<Post> for RssEntry {
-    fn from(post: Post) -> Self {
-        let full_url = format!("https://jonathansm.com/post/{}", post.id);
+impl RssEntry {
+    fn new(post: Post, base_url: &str) -> Self {
+        let full_url = escape(&format!("{base_url}/post/{}", post.id));
 
         let (title, content) = match post.content_type {
             ContentType::Post => (
@@ -30,6 +144,32 @@ impl From<Post> for RssEntry {
                 });
                 (title, format!("{}{}", link_html, post.content))
             }
+            ContentType::Note => (first_words(&post.content, NOTE_TITLE_WORDS), post.content),
+            ContentType::Photo => (
+                post.title.unwrap_or_else(|| "Photo".to_string()),
+                format!("{}{}", gallery_html(base_url, &post.images), post.content),
+            ),
+            ContentType::Reply => {
+                let target = post.in_reply_to.as_deref().unwrap_or_default();
+                let title = match &post.title {
+                    Some(title) => format!("Reply to {title}"),
+                    None if target.is_empty() => "Reply".to_string(),
+                    None => format!("Reply to {}", url_host(target)),
+                };
+                let context = post
+                    .reply_context
+                    .as_deref()
+                    .map_or_else(String::new, |context| {
+                        format!("<blockquote>{}</blockquote>", escape(context))
+                    });
+                let reply_html = if target.is_empty() {
+                    String::new()
+                } else {
+                    let target = escape(target);
+                    format!(r#"<p>In reply to <a href="{target}">{target}</a></p>"#)
+                };
+                (title, format!("{reply_html}{context}{}", post.content))
+            }
             ContentType::Quote => {
                 let author = post.quote_author.as_deref().unwrap_or("an unknown source");
                 let title = post
@@ -51,12 +191,38 @@ impl From<Post> for RssEntry {
             content,
             pub_date: post.date,
             guid: full_url,
+            images: post.images,
         }
     }
-}
 
-impl RssEntry {
-    fn to_xml(&self) -> String {
+    /// RSS allows only one `enclosure`, so it gets the first image.
+    fn media_xml(&self, base_url: &str) -> String {
+        let enclosure = self.images.first().map_or_else(String::new, |image| {
+            format!(
+                r#"<enclosure url="{}" length="{}" type="{}" />"#,
+                escape(&format!("{base_url}{}", image.src)),
+                image.length,
+                image.mime_type
+            )
+        });
+        let media: String = self
+            .images
+            .iter()
+            .map(|image| {
+                let description = image.caption.as_deref().unwrap_or(&image.alt);
+                format!(
+                    r#"<media:content url="{}" fileSize="{}" type="{}" medium="image"><media:description>{}</media:description></media:content>"#,
+                    escape(&format!("{base_url}{}", image.src)),
+                    image.length,
+                    image.mime_type,
+                    escape(description)
+                )
+            })
+            .collect();
+        format!("{enclosure}{media}")
+    }
+
+    fn to_xml(&self, base_url: &str) -> String {
         format!(
             r#"
             <item>
@@ -65,42 +231,89 @@ impl RssEntry {
                 <guid isPermalink="true">{}</guid>
                 <pubDate>{}</pubDate>
                 <content:encoded><![CDATA[{}]]></content:encoded>
+                {}
             </item>
             "#,
-            self.title, self.link, self.guid, self.pub_date, self.content
+            escape(&self.title),
+            self.link,
+            self.guid,
+            self.pub_date,
+            self.content,
+            self.media_xml(base_url)
         )
         .trim()
         .to_string()
     }
 }
 
-pub async fn feed(app: State<AppState>) -> impl IntoResponse {
-    let entries = app.0.post_service.get_rss_entries().await.unwrap();
-    let rss_items: String = entries
+/// RFC 6721 requires a deletion time, so tombstones without a readable one are left out.
+fn deleted_entry_xml(base_url: &str, tombstone: &Tombstone) -> Option<String> {
+    let Some(when) = deletion_time(&tombstone.deleted_at) else {
+        tracing::warn!(
+            "Leaving tombstone for {} out of the feed, unreadable deleted_at {}",
+            tombstone.post_id,
+            tombstone.deleted_at
+        );
+        return None;
+    };
+    let guid = escape(&format!("{base_url}/post/{}", tombstone.post_id));
+    Some(match &tombstone.reason {
+        Some(reason) => format!(
+            r#"<at:deleted-entry ref="{guid}" when="{when}"><at:comment>{}</at:comment></at:deleted-entry>"#,
+            escape(reason)
+        ),
+        None => format!(r#"<at:deleted-entry ref="{guid}" when="{when}" />"#),
+    })
+}
+
+/// SQLite's `datetime()` format is read as UTC.
+fn deletion_time(deleted_at: &str) -> Option<String> {
+    DateTime::parse_from_rfc3339(deleted_at)
+        .map(|time| time.to_rfc3339())
+        .or_else(|_| {
+            NaiveDateTime::parse_from_str(deleted_at, "%Y-%m-%d %H:%M:%S")
+                .map(|time| time.and_utc().to_rfc3339())
+        })
+        .ok()
+}
+
+pub async fn feed(app: State<AppState>) -> Result<impl IntoResponse> {
+    let base_url = &app.0.site.base_url;
+    let entries = app.0.post_service.get_rss_entries().await?;
+    let mut rss_items: String = entries
         .into_iter()
-        .map(RssEntry::from)
-        .map(|entry| entry.to_xml())
+        .map(|post| RssEntry::new(post, base_url).to_xml(base_url))
         .collect();
+    match app.0.post_service.get_recent_tombstones().await {
+        Ok(tombstones) => rss_items.extend(
+            tombstones
+                .iter()
+                .filter_map(|tombstone| deleted_entry_xml(base_url, tombstone)),
+        ),
+        Err(e) => tracing::error!("Failed to get tombstones for the feed: {}", e),
+    }
 
+    let title = escape(&app.0.site.title);
+    let link = escape(base_url);
     let rss = format!(
         r#"
         <?xml version="1.0" encoding="UTF-8" ?>
-        <rss version="2.0" xmlns:content="http://purl.org/rss/1.0/modules/content/" xmlns:atom="http://www.w3.org/2005/Atom">
+        <rss version="2.0" xmlns:content="http://purl.org/rss/1.0/modules/content/" xmlns:atom="http://www.w3.org/2005/Atom" xmlns:at="http://purl.org/atompub/tombstones/1.0" xmlns:media="http://search.yahoo.com/mrss/">
             <channel>
-                <title>Jonathan's Blog</title>
-                <link>https://jonathansm.com</link>
-                <description>Jonathan's Blog</description>
+                <title>{title}</title>
+                <link>{link}</link>
+                <description>{title}</description>
                 <language>en-us</language>
-                <atom:link href="https://jonathansm.com/feed" rel="self" type="application/rss+xml" />
+                <atom:link href="{link}/feed" rel="self" type="application/rss+xml" />
                 {rss_items}
             </channel>
         </rss>
         "#
     );
 
-    (
+    Ok((
         StatusCode::OK,
         [(header::CONTENT_TYPE, "application/atom+xml")],
         rss,
-    )
+    ))
 }
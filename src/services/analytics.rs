@@ -0,0 +1,156 @@
+use crate::error::Result;
+use chrono::Utc;
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::params;
+use serde::Serialize;
+use tokio::task;
+
+const MAX_QUERY_LEN: usize = 200;
+const REPORT_ROWS: i64 = 50;
+
+#[derive(Debug, Serialize)]
+pub struct QueryStat {
+    pub query: String,
+    pub searches: i64,
+    pub avg_results: f64,
+    pub last_seen: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClickStat {
+    pub post_id: String,
+    pub clicks: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchReport {
+    pub total_searches: i64,
+    pub top_queries: Vec<QueryStat>,
+    pub zero_result_queries: Vec<QueryStat>,
+    pub top_clicks: Vec<ClickStat>,
+}
+
+/// Nothing identifying is kept: queries are normalised, timestamps are cut down to the day and
+/// no request details are stored. Without a database every call is a no-op.
+#[derive(Clone, Debug)]
+pub struct AnalyticsService {
+    pool: Option<Pool<SqliteConnectionManager>>,
+}
+
+impl AnalyticsService {
+    pub fn new(pool: Option<Pool<SqliteConnectionManager>>) -> Self {
+        Self { pool }
+    }
+
+    fn normalize(query: &str) -> String {
+        query
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .to_lowercase()
+            .chars()
+            .take(MAX_QUERY_LEN)
+            .collect()
+    }
+
+    fn write<F>(&self, write_fn: F)
+    where
+        F: FnOnce(&rusqlite::Connection, &str) -> rusqlite::Result<usize> + Send + 'static,
+    {
+        let Some(pool) = self.pool.clone() else {
+            return;
+        };
+        task::spawn_blocking(move || {
+            let day = Utc::now().format("%Y-%m-%d").to_string();
+            let result = pool
+                .get()
+                .map_err(anyhow::Error::from)
+                .and_then(|conn| write_fn(&conn, &day).map_err(anyhow::Error::from));
+            if let Err(e) = result {
+                tracing::warn!("Failed to record search analytics: {}", e);
+            }
+        });
+    }
+
+    pub fn record_search(&self, query: &str, result_count: usize) {
+        let query = Self::normalize(query);
+        if query.is_empty() {
+            return;
+        }
+        #[allow(clippy::cast_possible_wrap)]
+        let result_count = result_count as i64;
+        self.write(move |conn, day| {
+            conn.execute(
+                "INSERT INTO searches (day, query, result_count) VALUES (?, ?, ?)",
+                params![day, query, result_count],
+            )
+        });
+    }
+
+    pub fn record_click(&self, query: &str, post_id: &str) {
+        let query = Self::normalize(query);
+        let post_id = post_id.to_owned();
+        self.write(move |conn, day| {
+            conn.execute(
+                "INSERT INTO search_clicks (day, query, post_id) VALUES (?, ?, ?)",
+                params![day, query, post_id],
+            )
+        });
+    }
+
+    pub async fn report(&self) -> Result<Option<SearchReport>> {
+        let Some(pool) = self.pool.clone() else {
+            return Ok(None);
+        };
+
+        task::spawn_blocking(move || {
+            let conn = pool.get()?;
+
+            let query_stats = |sql: &str| -> Result<Vec<QueryStat>> {
+                let mut stmt = conn.prepare(sql)?;
+                let stats = stmt
+                    .query_map([REPORT_ROWS], |row| {
+                        Ok(QueryStat {
+                            query: row.get(0)?,
+                            searches: row.get(1)?,
+                            avg_results: row.get(2)?,
+                            last_seen: row.get(3)?,
+                        })
+                    })?
+                    .collect::<rusqlite::Result<Vec<_>>>()?;
+                Ok(stats)
+            };
+
+            let total_searches = conn.query_row("SELECT COUNT(*) FROM searches", [], |row| {
+                row.get(0)
+            })?;
+            let top_queries = query_stats(
+                "SELECT query, COUNT(*) AS n, AVG(result_count), MAX(day) FROM searches GROUP BY query ORDER BY n DESC, query LIMIT ?",
+            )?;
+            let zero_result_queries = query_stats(
+                "SELECT query, COUNT(*) AS n, AVG(result_count), MAX(day) FROM searches GROUP BY query HAVING MAX(result_count) = 0 ORDER BY n DESC, query LIMIT ?",
+            )?;
+
+            let mut stmt = conn.prepare(
+                "SELECT post_id, COUNT(*) AS n FROM search_clicks GROUP BY post_id ORDER BY n DESC, post_id LIMIT ?",
+            )?;
+            let top_clicks = stmt
+                .query_map([REPORT_ROWS], |row| {
+                    Ok(ClickStat {
+                        post_id: row.get(0)?,
+                        clicks: row.get(1)?,
+                    })
+                })?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+
+            Ok(Some(SearchReport {
+                total_searches,
+                top_queries,
+                zero_result_queries,
+                top_clicks,
+            }))
+        })
+        .await?
+    }
+}
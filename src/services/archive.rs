@@ -0,0 +1,143 @@
+use super::post::{PostService, LISTED_POSTS};
+use crate::db::DbHandles;
+use crate::error::Result;
+use crate::post::SummaryPost;
+use chrono::Month;
+use serde::Serialize;
+use std::sync::Arc;
+use tokio::task;
+
+#[derive(Debug, Serialize)]
+pub struct ArchiveMonth {
+    pub month: u8,
+    pub name: &'static str,
+    pub count: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ArchiveYear {
+    pub year: u16,
+    pub count: usize,
+    pub months: Vec<ArchiveMonth>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ArchiveService {
+    db: Arc<DbHandles>,
+}
+
+impl ArchiveService {
+    pub fn new(db: Arc<DbHandles>) -> Self {
+        Self { db }
+    }
+
+    pub fn month_name(month: u8) -> Option<&'static str> {
+        Month::try_from(month).ok().map(|m| m.name())
+    }
+
+    /// Periods go by the date as written in the `date` column, without converting time zones.
+    pub async fn get_overview(&self) -> Result<Vec<ArchiveYear>> {
+        let pool = self.db.primary.load();
+
+        task::spawn_blocking(move || {
+            let conn = pool.get()?;
+            let mut stmt = conn.prepare(&format!(
+                r"
+                SELECT CAST(substr(date, 1, 4) AS INTEGER) AS year,
+                       CAST(substr(date, 6, 2) AS INTEGER) AS month,
+                       COUNT(*)
+                FROM posts
+                WHERE content_type != 'special' AND {LISTED_POSTS}
+                GROUP BY year, month
+                ORDER BY year DESC, month DESC
+                "
+            ))?;
+            let rows = stmt.query_map([], |row| {
+                Ok((
+                    row.get::<_, u16>(0)?,
+                    row.get::<_, u8>(1)?,
+                    row.get::<_, usize>(2)?,
+                ))
+            })?;
+
+            let mut years: Vec<ArchiveYear> = vec![];
+            for row in rows {
+                let (year, month, count) = row?;
+                let Some(name) = Self::month_name(month) else {
+                    tracing::warn!("Skipping posts with unreadable date {year}-{month}");
+                    continue;
+                };
+                if years.last().is_none_or(|last| last.year != year) {
+                    years.push(ArchiveYear {
+                        year,
+                        count: 0,
+                        months: vec![],
+                    });
+                }
+                if let Some(entry) = years.last_mut() {
+                    entry.count += count;
+                    entry.months.push(ArchiveMonth { month, name, count });
+                }
+            }
+            Ok(years)
+        })
+        .await?
+    }
+
+    pub async fn get_period(&self, year: u16, month: Option<u8>) -> Result<Vec<SummaryPost>> {
+        // ISO dates compare correctly as text, so a period is a half-open range of prefixes
+        let (start, end) = match month {
+            Some(12) => (format!("{year:04}-12"), format!("{:04}", year + 1)),
+            Some(month) => (
+                format!("{year:04}-{month:02}"),
+                format!("{year:04}-{:02}", month + 1),
+            ),
+            None => (format!("{year:04}"), format!("{:04}", year + 1)),
+        };
+        let pool = self.db.primary.load();
+
+        task::spawn_blocking(move || {
+            let conn = pool.get()?;
+            let mut stmt = conn.prepare(&format!(
+                r"
+                SELECT id, content_type, title, link, via, quote_author, date FROM posts
+                WHERE content_type != 'special' AND {LISTED_POSTS} AND date >= ? AND date < ?
+                ORDER BY date ASC
+                "
+            ))?;
+            let posts = stmt
+                .query_map([&start, &end], PostService::row_to_summary_post)?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            Ok(posts)
+        })
+        .await?
+    }
+
+    pub async fn get_on_this_day(
+        &self,
+        year: i32,
+        month: u32,
+        day: u32,
+    ) -> Result<Vec<SummaryPost>> {
+        let month_day = format!("{month:02}-{day:02}");
+        let year = format!("{year:04}");
+        let pool = self.db.primary.load();
+
+        task::spawn_blocking(move || {
+            let conn = pool.get()?;
+            let mut stmt = conn.prepare(&format!(
+                r"
+                SELECT id, content_type, title, link, via, quote_author, date FROM posts
+                WHERE content_type != 'special' AND {LISTED_POSTS}
+                AND substr(date, 6, 5) = ? AND substr(date, 1, 4) < ?
+                ORDER BY date DESC
+                "
+            ))?;
+            let posts = stmt
+                .query_map([&month_day, &year], PostService::row_to_summary_post)?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            Ok(posts)
+        })
+        .await?
+    }
+}
@@ -1,9 +1,20 @@
 use crate::db::DbHandles;
-use anyhow::Result;
+use crate::error::Result;
 use rusqlite::params;
 use std::sync::Arc;
 use tokio::task;
 
+pub fn mime_type(filename: &str) -> &'static str {
+    match filename.rsplit('.').next() {
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("svg") => "image/svg+xml",
+        _ => "application/octet-stream",
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct ImageService {
     db: Arc<DbHandles>,
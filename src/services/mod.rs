@@ -1,4 +1,12 @@
+pub mod analytics;
+pub mod archive;
 pub mod image;
+pub mod page;
 pub mod post;
+pub mod redirect;
 pub mod search;
 pub mod search_query;
+pub mod series;
+pub mod snapshot;
+pub mod suggest;
+pub mod tag;
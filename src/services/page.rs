@@ -0,0 +1,95 @@
+use super::snapshot::Snapshot;
+use crate::db::{table_exists, DbHandles};
+use crate::error::Result;
+use rusqlite::Connection;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SpecialPage {
+    pub slug: String,
+    pub title: String,
+    pub template: Option<String>,
+    /// `None` keeps it out of the header, unless the database has no `special_pages` table.
+    pub nav_position: Option<i64>,
+}
+
+#[derive(Debug, Default)]
+pub struct SpecialPages {
+    pages: HashMap<String, SpecialPage>,
+    nav: Vec<SpecialPage>,
+}
+
+impl SpecialPages {
+    fn build(conn: &Connection) -> Result<Self> {
+        let mut stmt = conn.prepare(
+            r"
+            SELECT posts.id, posts.title, special_pages.template, special_pages.nav_position
+            FROM posts
+            LEFT JOIN special_pages ON special_pages.post_id = posts.id
+            WHERE posts.content_type = 'special'
+            ORDER BY special_pages.nav_position, COALESCE(posts.title, posts.id)
+            ",
+        )?;
+        let pages = stmt
+            .query_map([], |row| {
+                let id: String = row.get(0)?;
+                Ok(SpecialPage {
+                    title: row
+                        .get::<_, Option<String>>(1)?
+                        .unwrap_or_else(|| id.clone()),
+                    slug: id,
+                    template: row.get::<_, Option<String>>(2)?.filter(|t| !t.is_empty()),
+                    nav_position: row.get(3)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        // Databases from before `special_pages` link every special page, as the header used to
+        let has_settings = table_exists(conn, "special_pages")?;
+        Ok(SpecialPages {
+            nav: pages
+                .iter()
+                .filter(|page| !has_settings || page.nav_position.is_some())
+                .cloned()
+                .collect(),
+            pages: pages
+                .into_iter()
+                .map(|page| (page.slug.clone(), page))
+                .collect(),
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PageService {
+    pages: Arc<Snapshot<SpecialPages>>,
+}
+
+impl PageService {
+    pub fn new(db: Arc<DbHandles>) -> Self {
+        Self {
+            pages: Arc::new(Snapshot::new(db)),
+        }
+    }
+
+    pub async fn rebuild(&self) -> Result<()> {
+        self.pages.rebuild(SpecialPages::build).await?;
+        Ok(())
+    }
+
+    pub fn get(&self, slug: &str) -> Option<SpecialPage> {
+        self.pages.load().pages.get(slug).cloned()
+    }
+
+    pub fn nav(&self) -> Vec<SpecialPage> {
+        self.pages.load().nav.clone()
+    }
+
+    pub fn all(&self) -> Vec<SpecialPage> {
+        let mut pages: Vec<SpecialPage> = self.pages.load().pages.values().cloned().collect();
+        pages.sort_by(|a, b| a.slug.cmp(&b.slug));
+        pages
+    }
+}
@@ -1,21 +1,36 @@
+use super::{snapshot::Snapshot, tag::TagTaxonomy};
 use crate::db::DbHandles;
-use crate::post::{Commit, ContentType, Post, SummaryPost};
-use anyhow::{Context, Result};
+use crate::error::{AppError, Result};
+use crate::post::{Commit, ContentType, Post, PostImage, SummaryPost, Tombstone, Visibility};
+use anyhow::Context;
 use r2d2_sqlite::SqliteConnectionManager;
-use rusqlite::params;
+use rusqlite::{params, OptionalExtension};
 use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::task;
 use tracing;
 
+/// Public posts and scheduled posts whose time has come, minus tombstoned ones. SQLite's clock
+/// decides, so scheduled posts go live without anything being rebuilt.
+pub const LISTED_POSTS: &str = "NOT EXISTS (SELECT 1 FROM post_visibility WHERE post_visibility.post_id = posts.id AND NOT (post_visibility.visibility = 'public' OR (post_visibility.visibility = 'scheduled' AND datetime(post_visibility.publish_at) <= datetime('now')))) AND NOT EXISTS (SELECT 1 FROM tombstones WHERE tombstones.post_id = posts.id)";
+
+pub const PUBLISHED_POSTS: &str = "NOT EXISTS (SELECT 1 FROM post_visibility WHERE post_visibility.post_id = posts.id AND NOT (post_visibility.visibility IN ('public', 'unlisted') OR (post_visibility.visibility = 'scheduled' AND datetime(post_visibility.publish_at) <= datetime('now')))) AND NOT EXISTS (SELECT 1 FROM tombstones WHERE tombstones.post_id = posts.id)";
+
+#[derive(Debug, Default)]
+pub struct PostScope {
+    pub tag: Option<String>,
+    pub content_type: Option<ContentType>,
+}
+
 #[derive(Debug, Clone)]
 pub struct PostService {
     db: Arc<DbHandles>,
+    taxonomy: Arc<Snapshot<TagTaxonomy>>,
 }
 
 impl PostService {
-    pub fn new(db: Arc<DbHandles>) -> Self {
-        Self { db }
+    pub fn new(db: Arc<DbHandles>, taxonomy: Arc<Snapshot<TagTaxonomy>>) -> Self {
+        Self { db, taxonomy }
     }
 
     async fn run_db_query<F, T>(&self, query_fn: F) -> Result<T>
@@ -32,10 +47,9 @@ impl PostService {
         .context("Failed to join blocking task")?
     }
 
-    pub fn row_to_post(row: &rusqlite::Row) -> rusqlite::Result<Post> {
+    pub fn row_to_post(row: &rusqlite::Row, taxonomy: &TagTaxonomy) -> rusqlite::Result<Post> {
         let id: String = row.get("id")?;
-        let content_type_str: String = row.get("content_type")?;
-        let content_type = ContentType::from(content_type_str);
+        let content_type: ContentType = row.get("content_type")?;
         let title: Option<String> = row.get("title")?;
         let link: Option<String> = row.get("link")?;
         let via: Option<String> = row.get("via")?;
@@ -46,7 +60,9 @@ impl PostService {
         let commits = commits_str.and_then(|s| serde_json::from_str(&s).ok());
 
         let tags_str: Option<String> = row.get("tags")?;
-        let tags = tags_str.and_then(|s| serde_json::from_str(&s).ok());
+        let tags = tags_str
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .map(|tags| taxonomy.normalize(tags));
 
         Ok(Post {
             id,
@@ -62,13 +78,21 @@ impl PostService {
             tags,
             real_commits: None,
             related_posts: None,
+            previous_post: None,
+            next_post: None,
+            pinned: false,
+            visibility: Visibility::Public,
+            publish_at: None,
+            images: vec![],
+            in_reply_to: None,
+            reply_context: None,
         })
     }
 
     pub fn row_to_summary_post(row: &rusqlite::Row) -> rusqlite::Result<SummaryPost> {
         Ok(SummaryPost {
             id: row.get("id")?,
-            content_type: ContentType::from(row.get::<_, String>("content_type")?),
+            content_type: row.get("content_type")?,
             title: row.get("title")?,
             link: row.get("link")?,
             via: row.get("via")?,
@@ -77,45 +101,85 @@ impl PostService {
         })
     }
 
-    pub async fn get_main_posts(&self) -> Result<Vec<Post>> {
+    /// `expires_at` is a date-time, or a date that keeps the post pinned to the end of that day UTC.
+    pub async fn get_main_posts(&self, count: usize) -> Result<Vec<Post>> {
+        let taxonomy = self.taxonomy.load_full();
+        #[allow(clippy::cast_possible_wrap)]
+        let count = count as i64;
         let queried = self
-            .run_db_query(|conn| {
-                let mut stmt = conn.prepare(
-                    "SELECT * FROM posts WHERE content_type != 'special' ORDER BY date DESC LIMIT 5",
-                )?;
-                let iter = stmt.query_map([], Self::row_to_post)?;
+            .run_db_query(move |conn| {
+                let mut stmt = conn.prepare(&format!(
+                    r"
+                    SELECT posts.*, pinned_posts.post_id IS NOT NULL AS pinned
+                    FROM posts
+                    LEFT JOIN pinned_posts ON pinned_posts.post_id = posts.id
+                        AND (
+                            pinned_posts.expires_at IS NULL
+                            OR CASE
+                                WHEN length(pinned_posts.expires_at) = 10
+                                    THEN datetime(pinned_posts.expires_at, '+1 day')
+                                ELSE datetime(pinned_posts.expires_at)
+                            END > datetime('now')
+                        )
+                    WHERE posts.content_type != 'special' AND {LISTED_POSTS}
+                    ORDER BY pinned DESC, pinned_posts.position, posts.date DESC
+                    LIMIT ?
+                    "
+                ))?;
+                let iter = stmt.query_map(params![count], |row| {
+                    let mut post = Self::row_to_post(row, &taxonomy)?;
+                    post.pinned = row.get("pinned")?;
+                    Ok(post)
+                })?;
                 iter.collect::<rusqlite::Result<Vec<_>>>()
-                    .map_err(anyhow::Error::from)
+                    .map_err(AppError::from)
             })
             .await?;
 
         self.bulk_convert_to_posts(queried).await
     }
 
-    pub async fn get_paginated_posts(&self, page: usize) -> Result<(Vec<Post>, usize, usize)> {
+    pub async fn get_paginated_posts(
+        &self,
+        page: usize,
+        content_type: Option<ContentType>,
+    ) -> Result<(Vec<Post>, usize, usize)> {
         const POSTS_PER_PAGE: i64 = 10;
         #[allow(clippy::cast_possible_wrap)]
         let offset = (page as i64 - 1) * POSTS_PER_PAGE;
 
+        let (conditions, values) = self.scope_conditions(&PostScope {
+            tag: None,
+            content_type,
+        });
+        let filter = conditions.join(" AND ");
+        let count_sql = format!("SELECT COUNT(*) FROM posts WHERE {filter}");
+        let mut page_values = values.clone();
+        page_values.extend([POSTS_PER_PAGE.to_string(), offset.to_string()]);
+
+        let taxonomy = self.taxonomy.load_full();
         let queried = self
             .run_db_query(move |conn| {
-                let mut stmt = conn.prepare(
-                    "SELECT * FROM posts WHERE content_type != 'special' ORDER BY date DESC LIMIT ? OFFSET ?",
-                )?;
-                let iter = stmt.query_map(params![POSTS_PER_PAGE, offset], Self::row_to_post)?;
+                let mut stmt = conn.prepare(&format!(
+                    "SELECT * FROM posts WHERE {filter} ORDER BY date DESC LIMIT ? OFFSET ?"
+                ))?;
+                let iter = stmt
+                    .query_map(rusqlite::params_from_iter(page_values.iter()), |row| {
+                        Self::row_to_post(row, &taxonomy)
+                    })?;
                 iter.collect::<rusqlite::Result<Vec<_>>>()
-                    .map_err(anyhow::Error::from)
+                    .map_err(AppError::from)
             })
             .await?;
 
         let total_posts: i64 = self
-            .run_db_query(|conn| {
+            .run_db_query(move |conn| {
                 conn.query_row(
-                    "SELECT COUNT(*) FROM posts WHERE content_type != 'special'",
-                    [],
+                    &count_sql,
+                    rusqlite::params_from_iter(values.iter()),
                     |row| row.get(0),
                 )
-                .map_err(anyhow::Error::from)
+                .map_err(AppError::from)
             })
             .await?;
 
@@ -126,32 +190,46 @@ impl PostService {
         Ok((posts, page, total_pages))
     }
 
-    async fn get_post_by_id_internal(
-        &self,
-        id: &str,
-        content_type_sql: &str,
-        not_found_msg: &str,
-    ) -> Result<Post> {
+    async fn get_post_by_id_internal(&self, id: &str, condition_sql: &str) -> Result<Post> {
         let id_owned = id.to_owned();
-        let query_sql = format!("SELECT * FROM posts WHERE id = ? AND {content_type_sql}");
-        let not_found_msg_owned = not_found_msg.to_owned();
+        let query_sql = format!(
+            r"
+            SELECT posts.*, post_visibility.publish_at,
+                CASE
+                    WHEN post_visibility.visibility = 'scheduled'
+                        AND datetime(post_visibility.publish_at) <= datetime('now') THEN 'public'
+                    ELSE COALESCE(post_visibility.visibility, 'public')
+                END AS visibility
+            FROM posts
+            LEFT JOIN post_visibility ON post_visibility.post_id = posts.id
+            WHERE posts.id = ? AND {condition_sql}
+            "
+        );
+        let taxonomy = self.taxonomy.load_full();
 
         self.run_db_query(move |conn| {
-            conn.query_row(&query_sql, [&id_owned], Self::row_to_post)
-                .map_err(|e| match e {
-                    rusqlite::Error::QueryReturnedNoRows => {
-                        anyhow::anyhow!("{}: {}", not_found_msg_owned, id_owned)
-                    }
-                    _ => e.into(),
-                })
+            conn.query_row(&query_sql, [&id_owned], |row| {
+                let mut post = Self::row_to_post(row, &taxonomy)?;
+                post.visibility = Visibility::from(row.get::<_, String>("visibility")?);
+                post.publish_at = row.get("publish_at")?;
+                Ok(post)
+            })
+            .map_err(|e| match e {
+                rusqlite::Error::QueryReturnedNoRows => AppError::NotFound,
+                _ => e.into(),
+            })
         })
         .await
     }
 
-    pub async fn get_post(&self, id: &str) -> Result<Post> {
-        let query = self
-            .get_post_by_id_internal(id, "content_type != 'special'", "Post not found")
-            .await?;
+    /// Drafts and posts scheduled for later are only found when previewing.
+    pub async fn get_post(&self, id: &str, scope: &PostScope, preview: bool) -> Result<Post> {
+        let condition = if preview {
+            "posts.content_type != 'special'".to_string()
+        } else {
+            format!("posts.content_type != 'special' AND {PUBLISHED_POSTS}")
+        };
+        let query = self.get_post_by_id_internal(id, &condition).await?;
         let mut post = self.convert_to_post(query).await?;
 
         match self.get_related_posts(&post.id).await {
@@ -165,12 +243,22 @@ impl PostService {
             }
         }
 
+        match self.get_neighbours(&post, scope).await {
+            Ok((previous_post, next_post)) => {
+                post.previous_post = previous_post;
+                post.next_post = next_post;
+            }
+            Err(e) => {
+                tracing::error!("Failed to get neighbouring posts for {}: {}", post.id, e);
+            }
+        }
+
         Ok(post)
     }
 
     pub async fn get_special_page(&self, id: &str) -> Result<Post> {
         let query = self
-            .get_post_by_id_internal(id, "content_type = 'special'", "Special page not found")
+            .get_post_by_id_internal(id, "content_type = 'special'")
             .await?;
         self.convert_to_post(query).await
     }
@@ -203,7 +291,7 @@ impl PostService {
 
                 ids_iter
                     .collect::<rusqlite::Result<Vec<_>>>()
-                    .map_err(anyhow::Error::from)
+                    .map_err(AppError::from)
             })
             .await?;
 
@@ -219,7 +307,7 @@ impl PostService {
                     .map(|_| "?")
                     .collect::<Vec<_>>()
                     .join(",");
-                let sql = format!("SELECT id, content_type, title, link, via, quote_author, date FROM posts WHERE id IN ({placeholders})");
+                let sql = format!("SELECT id, content_type, title, link, via, quote_author, date FROM posts WHERE id IN ({placeholders}) AND {LISTED_POSTS}");
                 let mut stmt = conn.prepare(&sql)?;
 
                 let post_iter = stmt.query_map(
@@ -229,7 +317,7 @@ impl PostService {
 
                 post_iter
                     .collect::<rusqlite::Result<Vec<_>>>()
-                    .map_err(anyhow::Error::from)
+                    .map_err(AppError::from)
             })
             .await?;
 
@@ -246,15 +334,113 @@ impl PostService {
         Ok(ordered_posts)
     }
 
+    fn scope_conditions(&self, scope: &PostScope) -> (Vec<String>, Vec<String>) {
+        let mut conditions = vec![
+            "content_type != 'special'".to_string(),
+            LISTED_POSTS.to_string(),
+        ];
+        let mut values: Vec<String> = vec![];
+        if let Some(tag) = &scope.tag {
+            let tags = self.taxonomy.load().expand(tag);
+            let placeholders = tags.iter().map(|_| "?").collect::<Vec<_>>().join(", ");
+            conditions.push(format!(
+                "EXISTS (SELECT 1 FROM json_each(posts.tags) WHERE value IN ({placeholders}))"
+            ));
+            values.extend(tags);
+        }
+        if let Some(content_type) = scope.content_type {
+            conditions.push("content_type = ?".to_string());
+            values.push(content_type.into());
+        }
+        (conditions, values)
+    }
+
+    pub async fn get_random_post_id(&self, scope: &PostScope) -> Result<Option<String>> {
+        let (conditions, values) = self.scope_conditions(scope);
+        let sql = format!(
+            "SELECT id FROM posts WHERE {} ORDER BY RANDOM() LIMIT 1",
+            conditions.join(" AND ")
+        );
+
+        self.run_db_query(move |conn| {
+            conn.query_row(&sql, rusqlite::params_from_iter(values.iter()), |row| {
+                row.get(0)
+            })
+            .optional()
+            .map_err(AppError::from)
+        })
+        .await
+    }
+
+    async fn get_neighbours(
+        &self,
+        post: &Post,
+        scope: &PostScope,
+    ) -> Result<(Option<SummaryPost>, Option<SummaryPost>)> {
+        let (conditions, mut values) = self.scope_conditions(scope);
+        let filter = conditions.join(" AND ");
+        values.extend([post.date.clone(), post.id.clone()]);
+
+        self.run_db_query(move |conn| {
+            let neighbour = |comparison: &str, direction: &str| {
+                conn.query_row(
+                    &format!(
+                        "SELECT id, content_type, title, link, via, quote_author, date FROM posts WHERE {filter} AND (date, id) {comparison} (?, ?) ORDER BY date {direction}, id {direction} LIMIT 1"
+                    ),
+                    rusqlite::params_from_iter(values.iter()),
+                    Self::row_to_summary_post,
+                )
+                .optional()
+            };
+            Ok((neighbour("<", "DESC")?, neighbour(">", "ASC")?))
+        })
+        .await
+    }
+
+    fn row_to_tombstone(row: &rusqlite::Row) -> rusqlite::Result<Tombstone> {
+        Ok(Tombstone {
+            post_id: row.get("post_id")?,
+            deleted_at: row.get("deleted_at")?,
+            reason: row.get("reason")?,
+        })
+    }
+
+    pub async fn get_tombstone(&self, id: &str) -> Result<Option<Tombstone>> {
+        let id = id.to_owned();
+        self.run_db_query(move |conn| {
+            conn.query_row(
+                "SELECT post_id, deleted_at, reason FROM tombstones WHERE post_id = ?",
+                [&id],
+                Self::row_to_tombstone,
+            )
+            .optional()
+            .map_err(AppError::from)
+        })
+        .await
+    }
+
+    pub async fn get_recent_tombstones(&self) -> Result<Vec<Tombstone>> {
+        self.run_db_query(|conn| {
+            let mut stmt = conn.prepare(
+                "SELECT post_id, deleted_at, reason FROM tombstones ORDER BY deleted_at DESC LIMIT 20",
+            )?;
+            let iter = stmt.query_map([], Self::row_to_tombstone)?;
+            iter.collect::<rusqlite::Result<Vec<_>>>()
+                .map_err(AppError::from)
+        })
+        .await
+    }
+
     pub async fn get_rss_entries(&self) -> Result<Vec<Post>> {
+        let taxonomy = self.taxonomy.load_full();
         let posts = self
-            .run_db_query(|conn| {
-                let mut stmt = conn.prepare(
-                    "SELECT * FROM posts WHERE content_type != 'special' ORDER BY date DESC LIMIT 20",
-                )?;
-                let iter = stmt.query_map([], Self::row_to_post)?;
+            .run_db_query(move |conn| {
+                let mut stmt = conn.prepare(&format!(
+                    "SELECT * FROM posts WHERE content_type != 'special' AND {LISTED_POSTS} ORDER BY date DESC LIMIT 20"
+                ))?;
+                let iter = stmt.query_map([], |row| Self::row_to_post(row, &taxonomy))?;
                 iter.collect::<rusqlite::Result<Vec<_>>>()
-                    .map_err(anyhow::Error::from)
+                    .map_err(AppError::from)
             })
             .await?;
 
@@ -269,17 +455,105 @@ impl PostService {
 
     pub async fn get_all_post_urls(&self) -> Result<Vec<(String, String)>> {
         self.run_db_query(|conn| {
-            let mut stmt = conn.prepare(
-                "SELECT id, date FROM posts WHERE content_type != 'special' ORDER BY date DESC",
-            )?;
+            let mut stmt = conn.prepare(&format!(
+                "SELECT id, date FROM posts WHERE content_type != 'special' AND {LISTED_POSTS} ORDER BY date DESC"
+            ))?;
             let iter = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
             iter.collect::<rusqlite::Result<Vec<_>>>()
-                .map_err(anyhow::Error::from)
+                .map_err(AppError::from)
+        })
+        .await
+    }
+
+    async fn get_post_images(&self, ids: Vec<String>) -> Result<HashMap<String, Vec<PostImage>>> {
+        self.run_db_query(move |conn| {
+            let placeholders = ids.iter().map(|_| "?").collect::<Vec<_>>().join(",");
+            let mut stmt = conn.prepare(&format!(
+                r"
+                SELECT post_images.post_id, post_images.filename, post_images.alt,
+                       post_images.caption, length(images.data)
+                FROM post_images
+                INNER JOIN images ON images.filename = post_images.filename
+                WHERE post_images.post_id IN ({placeholders})
+                ORDER BY post_images.position, post_images.filename
+                "
+            ))?;
+            let rows = stmt.query_map(rusqlite::params_from_iter(ids.iter()), |row| {
+                let filename: String = row.get(1)?;
+                let image = PostImage {
+                    src: format!("/{filename}"),
+                    alt: row.get::<_, Option<String>>(2)?.unwrap_or_default(),
+                    caption: row.get(3)?,
+                    mime_type: crate::services::image::mime_type(&filename),
+                    length: row.get(4)?,
+                };
+                Ok((row.get::<_, String>(0)?, image))
+            })?;
+
+            let mut map: HashMap<String, Vec<PostImage>> = HashMap::new();
+            for row in rows {
+                let (post_id, image) = row?;
+                map.entry(post_id).or_default().push(image);
+            }
+            Ok(map)
+        })
+        .await
+    }
+
+    /// Replies without a `post_replies` row fall back to their `link`, where link-style replies
+    /// used to keep it.
+    async fn get_reply_targets(
+        &self,
+        ids: Vec<String>,
+    ) -> Result<HashMap<String, (Option<String>, Option<String>)>> {
+        self.run_db_query(move |conn| {
+            let placeholders = ids.iter().map(|_| "?").collect::<Vec<_>>().join(",");
+            let mut stmt = conn.prepare(&format!(
+                r"
+                SELECT posts.id, COALESCE(post_replies.in_reply_to, posts.link),
+                       post_replies.context
+                FROM posts
+                LEFT JOIN post_replies ON post_replies.post_id = posts.id
+                WHERE posts.id IN ({placeholders})
+                "
+            ))?;
+            let rows = stmt.query_map(rusqlite::params_from_iter(ids.iter()), |row| {
+                Ok((row.get(0)?, (row.get(1)?, row.get(2)?)))
+            })?;
+            rows.collect::<rusqlite::Result<HashMap<_, _>>>()
+                .map_err(AppError::from)
         })
         .await
     }
 
     pub async fn bulk_convert_to_posts(&self, mut posts: Vec<Post>) -> Result<Vec<Post>> {
+        let reply_ids: Vec<String> = posts
+            .iter()
+            .filter(|post| matches!(post.content_type, ContentType::Reply))
+            .map(|post| post.id.clone())
+            .collect();
+        if !reply_ids.is_empty() {
+            let mut targets = self.get_reply_targets(reply_ids).await?;
+            for post in &mut posts {
+                if let Some((in_reply_to, context)) = targets.remove(&post.id) {
+                    post.in_reply_to = in_reply_to;
+                    post.reply_context = context;
+                }
+            }
+        }
+
+        let photo_ids: Vec<String> = posts
+            .iter()
+            .filter(|post| matches!(post.content_type, ContentType::Photo))
+            .map(|post| post.id.clone())
+            .collect();
+        if !photo_ids.is_empty() {
+            let mut images = self.get_post_images(photo_ids).await?;
+            for post in &mut posts {
+                post.images = images.remove(&post.id).unwrap_or_default();
+            }
+        }
+
         let all_commit_ids: Vec<_> = posts
             .iter()
             .filter_map(|post| post.commits.as_ref())
@@ -322,7 +596,7 @@ impl PostService {
                     let commit = commit?;
                     map.insert(commit.id.clone(), commit);
                 }
-                anyhow::Result::<_>::Ok(map)
+                Result::<_>::Ok(map)
             })
             .await?
         };
@@ -0,0 +1,251 @@
+use super::snapshot::Snapshot;
+use crate::db::DbHandles;
+use crate::error::Result;
+use axum::http::HeaderValue;
+use rusqlite::Connection;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+const MAX_HOPS: usize = 10;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RedirectTarget {
+    Moved { location: String, permanent: bool },
+    Gone,
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    /// `None` for paths that answer 410.
+    to: Option<String>,
+    permanent: bool,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct RedirectReport {
+    pub total: usize,
+    pub chains: Vec<Vec<String>>,
+    pub loops: Vec<Vec<String>>,
+    pub invalid: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct RedirectMap {
+    rules: HashMap<String, Rule>,
+    report: RedirectReport,
+}
+
+impl RedirectMap {
+    fn build(conn: &Connection) -> Result<Self> {
+        let mut map = RedirectMap::default();
+
+        // Renamed posts first, so explicit rules for the same path replace them
+        let mut stmt = conn.prepare("SELECT old_id, post_id FROM post_slug_history")?;
+        for row in stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get(1)?)))? {
+            let (old_id, post_id): (String, String) = row?;
+            let to = format!("/post/{post_id}");
+            if HeaderValue::from_str(&to).is_err() {
+                map.report.invalid.push(format!(
+                    "/post/{old_id}: target {to:?} can't be sent as a Location"
+                ));
+                continue;
+            }
+            map.rules.insert(
+                normalize_path(&format!("/post/{old_id}")),
+                Rule {
+                    to: Some(to),
+                    permanent: true,
+                },
+            );
+        }
+
+        let mut stmt = conn.prepare("SELECT from_path, to_path, status FROM redirects")?;
+        let rows = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .collect::<rusqlite::Result<Vec<(String, Option<String>, u16)>>>()?;
+        for (from, to, status) in rows {
+            if let Some(to) = to
+                .as_deref()
+                .filter(|to| HeaderValue::from_str(to).is_err())
+            {
+                map.report
+                    .invalid
+                    .push(format!("{from}: target {to:?} can't be sent as a Location"));
+                continue;
+            }
+            let rule = match (status, to) {
+                (410, _) => Rule {
+                    to: None,
+                    permanent: true,
+                },
+                (301 | 308, Some(to)) => Rule {
+                    to: Some(to),
+                    permanent: true,
+                },
+                (302 | 307, Some(to)) => Rule {
+                    to: Some(to),
+                    permanent: false,
+                },
+                (status, to) => {
+                    map.report.invalid.push(format!(
+                        "{from}: status {status} with target {}",
+                        to.as_deref().unwrap_or("(none)")
+                    ));
+                    continue;
+                }
+            };
+            map.rules.insert(normalize_path(&from), rule);
+        }
+
+        // Rules only apply to paths that would otherwise 404, and following one through a post
+        // that exists would send readers away from it
+        let mut stmt = conn.prepare("SELECT id FROM posts")?;
+        for id in stmt.query_map([], |row| row.get::<_, String>(0))? {
+            let path = format!("/post/{}", id?);
+            if map.rules.remove(&normalize_path(&path)).is_some() {
+                map.report
+                    .invalid
+                    .push(format!("{path}: shadowed by an existing post"));
+            }
+        }
+
+        map.report.total = map.rules.len();
+        let mut seen_loops: HashSet<Vec<String>> = HashSet::new();
+        let mut sources: Vec<&String> = map.rules.keys().collect();
+        sources.sort();
+        for source in sources {
+            let (hops, looped) = map.walk(source);
+            if looped {
+                // Report each loop once, however many paths lead into it
+                let last = hops.last().cloned().unwrap_or_default();
+                let start = hops.iter().position(|hop| *hop == last).unwrap_or(0);
+                let mut cycle = hops[start..hops.len() - 1].to_vec();
+                cycle.sort();
+                if seen_loops.insert(cycle) {
+                    map.report.loops.push(hops);
+                }
+            } else if hops.len() > 2 {
+                map.report.chains.push(hops);
+            }
+        }
+
+        Ok(map)
+    }
+
+    fn walk(&self, from: &str) -> (Vec<String>, bool) {
+        let mut hops = vec![from.to_owned()];
+        while let Some(Rule { to: Some(to), .. }) = hops.last().and_then(|hop| self.rules.get(hop))
+        {
+            let next = normalize_path(to);
+            let looped = hops.contains(&next) || hops.len() > MAX_HOPS;
+            hops.push(next);
+            if looped {
+                return (hops, true);
+            }
+        }
+        (hops, false)
+    }
+
+    /// Follows chains to their end so readers get a single redirect. Loops resolve to nothing.
+    pub fn resolve(&self, path: &str) -> Option<RedirectTarget> {
+        let path = normalize_path(path);
+        let first = self.rules.get(&path)?;
+        let mut permanent = first.permanent;
+        let Some(mut location) = first.to.clone() else {
+            return Some(RedirectTarget::Gone);
+        };
+
+        let mut seen = HashSet::from([path]);
+        while let Some(rule) = self.rules.get(&normalize_path(&location)) {
+            if !seen.insert(normalize_path(&location)) || seen.len() > MAX_HOPS {
+                return None;
+            }
+            permanent &= rule.permanent;
+            match &rule.to {
+                Some(to) => location.clone_from(to),
+                None => return Some(RedirectTarget::Gone),
+            }
+        }
+
+        Some(RedirectTarget::Moved {
+            location,
+            permanent,
+        })
+    }
+
+    pub fn report(&self) -> &RedirectReport {
+        &self.report
+    }
+}
+
+/// Trailing slashes and percent-encoding don't matter, since some routes see the path before
+/// axum decodes it and some after.
+fn normalize_path(path: &str) -> String {
+    match percent_decode(path).trim_end_matches('/') {
+        "" => "/".to_string(),
+        trimmed => trimmed.to_string(),
+    }
+}
+
+fn percent_decode(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| path.get(i + 1..i + 3))
+            .flatten()
+            .filter(|hex| hex.bytes().all(|b| b.is_ascii_hexdigit()))
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).unwrap_or_else(|_| path.to_owned())
+}
+
+#[derive(Clone, Debug)]
+pub struct RedirectService {
+    map: Arc<Snapshot<RedirectMap>>,
+}
+
+impl RedirectService {
+    pub fn new(db: Arc<DbHandles>) -> Self {
+        Self {
+            map: Arc::new(Snapshot::new(db)),
+        }
+    }
+
+    /// Also logs any loops, chains and invalid rules it finds.
+    pub async fn rebuild(&self) -> Result<()> {
+        let map = self.map.rebuild(RedirectMap::build).await?;
+
+        let report = map.report();
+        for hops in &report.loops {
+            tracing::warn!("Redirect loop: {}", hops.join(" -> "));
+        }
+        if !report.chains.is_empty() {
+            tracing::info!("{} redirect chains will be collapsed", report.chains.len());
+        }
+        for invalid in &report.invalid {
+            tracing::warn!("Skipped invalid redirect {}", invalid);
+        }
+        Ok(())
+    }
+
+    pub fn resolve(&self, path: &str) -> Option<RedirectTarget> {
+        self.map.load().resolve(path)
+    }
+
+    pub fn report(&self) -> RedirectReport {
+        self.map.load().report().clone()
+    }
+}
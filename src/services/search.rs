@@ -1,35 +1,139 @@
-use super::{post::PostService, search_query::SearchQuery};
-use crate::db::DbHandles;
+use super::{
+    post::{PostService, LISTED_POSTS},
+    search_query::{SearchQuery, SortOrder},
+    snapshot::Snapshot,
+    tag::TagTaxonomy,
+};
+use crate::db::{table_exists, DbHandles};
+use crate::post::SummaryPost;
 use anyhow::Context;
+use rusqlite::{params, types::Value, Connection, OptionalExtension};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Write};
 use std::sync::Arc;
 use tokio::task;
 
+const RRF_K: f64 = 60.0;
+const VECTOR_CANDIDATES: i64 = 50;
+const FTS5_QUERY_ERRORS: &[&str] = &[
+    "fts5: ",
+    "unterminated string",
+    "no such column: ",
+    "unknown special query: ",
+    "expected integer, got ",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchCursor {
+    pub key: String,
+    pub id: String,
+}
+
+impl SearchCursor {
+    pub fn encode(&self) -> String {
+        format!("{}\u{1f}{}", self.key, self.id)
+            .bytes()
+            .fold(String::new(), |mut out, b| {
+                let _ = write!(out, "{b:02x}");
+                out
+            })
+    }
+
+    pub fn decode(encoded: &str) -> Option<Self> {
+        let bytes = (0..encoded.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(encoded.get(i..i + 2)?, 16).ok())
+            .collect::<Option<Vec<u8>>>()?;
+        let decoded = String::from_utf8(bytes).ok()?;
+        let (key, id) = decoded.split_once('\u{1f}')?;
+        Some(Self {
+            key: key.to_owned(),
+            id: id.to_owned(),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct SearchPage {
+    pub posts: Vec<SummaryPost>,
+    pub total: usize,
+    pub next_cursor: Option<SearchCursor>,
+}
+
+#[derive(Debug)]
+pub enum SearchError {
+    Syntax(String),
+    Database(anyhow::Error),
+}
+
+impl fmt::Display for SearchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SearchError::Syntax(msg) => write!(f, "Invalid search syntax: {msg}"),
+            SearchError::Database(e) => write!(f, "Search execution failed: {e:#}"),
+        }
+    }
+}
+
+impl std::error::Error for SearchError {}
+
+impl From<anyhow::Error> for SearchError {
+    fn from(e: anyhow::Error) -> Self {
+        SearchError::Database(e)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct FacetCount {
+    pub value: String,
+    pub count: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchFacets {
+    pub tags: Vec<FacetCount>,
+    pub content_types: Vec<FacetCount>,
+}
+
+#[derive(Debug, Default)]
+struct SqlFilters {
+    post_types: Vec<String>,
+    tag_groups: Vec<Vec<String>>,
+}
+
 #[derive(Clone, Debug)]
 pub struct SearchService {
     db: Arc<DbHandles>,
+    taxonomy: Arc<Snapshot<TagTaxonomy>>,
 }
 
 impl SearchService {
-    pub fn new(db: Arc<DbHandles>) -> Self {
-        Self { db }
+    pub fn new(db: Arc<DbHandles>, taxonomy: Arc<Snapshot<TagTaxonomy>>) -> Self {
+        Self { db, taxonomy }
     }
 
-    fn build_search_query(
+    fn build_filter_conditions(
         owned_query: &SearchQuery,
-        post_types_as_strings: &[String],
-    ) -> (String, Vec<Box<dyn rusqlite::ToSql>>) {
-        let mut conditions = vec![];
+        filters: &SqlFilters,
+        include_text: bool,
+    ) -> (Vec<String>, Vec<Box<dyn rusqlite::ToSql>>) {
+        let mut conditions = vec![LISTED_POSTS.to_string()];
         let mut params: Vec<Box<dyn rusqlite::ToSql>> = vec![];
 
-        if !owned_query.text_query.is_empty() {
+        if include_text && !owned_query.text_query.is_empty() {
             conditions.push("posts_fts MATCH ?".to_string());
             params.push(Box::new(owned_query.text_query.clone()));
         }
 
-        for tag in &owned_query.tags {
-            conditions
-                .push("EXISTS (SELECT 1 FROM json_each(posts.tags) WHERE value = ?)".to_string());
-            params.push(Box::new(tag.clone()));
+        for group in &filters.tag_groups {
+            let placeholders = group.iter().map(|_| "?").collect::<Vec<_>>().join(", ");
+            conditions.push(format!(
+                "EXISTS (SELECT 1 FROM json_each(posts.tags) WHERE value IN ({placeholders}))"
+            ));
+            for tag in group {
+                params.push(Box::new(tag.clone()));
+            }
         }
 
         if let Some(date) = &owned_query.from_date {
@@ -41,106 +145,516 @@ impl SearchService {
             params.push(Box::new(date.clone()));
         }
 
-        if !post_types_as_strings.is_empty() {
-            let placeholders = post_types_as_strings
+        if !filters.post_types.is_empty() {
+            let placeholders = filters
+                .post_types
                 .iter()
                 .map(|_| "?")
                 .collect::<Vec<_>>()
                 .join(", ");
             conditions.push(format!("posts.content_type IN ({placeholders})"));
-            for pt_str in post_types_as_strings {
+            for pt_str in &filters.post_types {
                 params.push(Box::new(pt_str.clone()));
             }
         }
 
+        (conditions, params)
+    }
+
+    fn build_search_query(
+        owned_query: &SearchQuery,
+        filters: &SqlFilters,
+        after: Option<&SearchCursor>,
+    ) -> anyhow::Result<(String, Vec<Box<dyn rusqlite::ToSql>>)> {
+        let (mut conditions, mut params) =
+            Self::build_filter_conditions(owned_query, filters, true);
+
+        let (sort_expr, ascending) = Self::sort_column(owned_query.sort);
+        let direction = if ascending { "ASC" } else { "DESC" };
+
+        // Keyset pagination: continue strictly after the last row of the previous page so a
+        // database swap between pages can't shift or repeat results.
+        if let Some(cursor) = after {
+            let comparison = if ascending { ">" } else { "<" };
+            conditions.push(format!("({sort_expr}, posts.id) {comparison} (?, ?)"));
+            if owned_query.sort == SortOrder::Relevance {
+                let score: f64 = cursor.key.parse().context("Invalid relevance cursor")?;
+                params.push(Box::new(score));
+            } else {
+                params.push(Box::new(cursor.key.clone()));
+            }
+            params.push(Box::new(cursor.id.clone()));
+        }
+
         let where_clause = if conditions.is_empty() {
             String::new()
         } else {
             format!("WHERE {}", conditions.join(" AND "))
         };
 
-        let order_clause = if owned_query.text_query.is_empty() {
-            "ORDER BY date DESC".to_string()
-        } else {
-            "ORDER BY rank".to_string()
-        };
+        let order_clause = format!("ORDER BY sort_key {direction}, posts.id {direction}");
 
-        (format!("{where_clause} {order_clause}"), params)
+        Ok((format!("{where_clause} {order_clause}"), params))
     }
 
-    pub async fn search(
-        &self,
-        query: &SearchQuery,
-        page: usize,
-        per_page: usize,
-    ) -> anyhow::Result<(Vec<crate::post::SummaryPost>, usize)> {
-        // Create a full clone of the query data to move into the thread
+    /// Relevance expects a text query, the caller maps it to `Newest` when there isn't one.
+    fn sort_column(sort: SortOrder) -> (&'static str, bool) {
+        match sort {
+            SortOrder::Newest => ("posts.date", false),
+            SortOrder::Oldest => ("posts.date", true),
+            // Same rule as `PostService::bulk_convert_to_posts`: the first commit is the latest
+            SortOrder::Updated => (
+                "COALESCE((SELECT commits.date FROM commits WHERE commits.id = json_extract(posts.commits, '$[0]')), posts.date)",
+                false,
+            ),
+            SortOrder::Relevance | SortOrder::Hybrid => ("bm25(posts_fts)", true),
+        }
+    }
+
+    fn to_owned_parts(&self, query: &SearchQuery) -> (SearchQuery, SqlFilters) {
         let owned_query = SearchQuery {
             text_query: query.text_query.clone(),
             tags: query.tags.clone(),
             from_date: query.from_date.clone(),
             to_date: query.to_date.clone(),
             post_type: Vec::default(),
+            sort: query.sort,
+            warnings: Vec::default(),
+        };
+        let taxonomy = self.taxonomy.load();
+        let filters = SqlFilters {
+            post_types: query
+                .post_type
+                .iter()
+                .map(|pt| pt.to_owned().into())
+                .collect(),
+            tag_groups: query.tags.iter().map(|tag| taxonomy.expand(tag)).collect(),
         };
-        let post_types_as_strings: Vec<String> = query
-            .post_type
-            .iter()
-            .map(|pt| pt.to_owned().into())
-            .collect();
-        let offset = (page - 1) * per_page;
+        (owned_query, filters)
+    }
+
+    /// Hybrid results that only matched by embedding aren't counted.
+    pub async fn facets(&self, query: &SearchQuery) -> crate::error::Result<SearchFacets> {
+        let (owned_query, filters) = self.to_owned_parts(query);
         let pool = self.db.primary.load();
+        let taxonomy = self.taxonomy.load_full();
 
-        let (posts, total) = task::spawn_blocking(move || {
+        task::spawn_blocking(move || {
             let conn = pool.get()?;
             let base_query = if owned_query.text_query.is_empty() {
                 "FROM posts".to_string()
             } else {
                 "FROM posts INNER JOIN posts_fts ON posts.id = posts_fts.id".to_string()
             };
-
-            let (filter_clauses, mut params) =
-                Self::build_search_query(&owned_query, &post_types_as_strings);
-
-            // Prepare count query first (borrows params immutably)
-            let count_query = format!("SELECT COUNT(*) {base_query} {filter_clauses}");
-            let total: i64 = conn.query_row(
-                &count_query,
-                rusqlite::params_from_iter(params.iter().map(|p| &**p)),
-                |r| r.get(0),
-            )?;
-
-            // Main query to fetch posts (takes ownership of params)
-            let posts_query = if owned_query.text_query.is_empty() {
-                format!(
-                    "SELECT posts.id, posts.content_type, posts.title, posts.link, posts.via, posts.quote_author, posts.date {base_query} {filter_clauses} LIMIT ? OFFSET ?"
-                )
+            let (conditions, params) =
+                Self::build_filter_conditions(&owned_query, &filters, true);
+            let where_clause = if conditions.is_empty() {
+                String::new()
             } else {
-                format!(
-                    "SELECT posts.id, posts.content_type, posts.title, posts.link, posts.via, posts.quote_author, posts.date, bm25(posts_fts) AS rank {base_query} {filter_clauses} LIMIT ? OFFSET ?"
-                )
+                format!("WHERE {}", conditions.join(" AND "))
             };
 
-            let mut stmt = conn.prepare(&posts_query)?;
-            #[allow(clippy::cast_possible_wrap)]
-            params.push(Box::new(per_page as i64));
-            #[allow(clippy::cast_possible_wrap)]
-            params.push(Box::new(offset as i64));
+            let count_facet = |sql: String| -> anyhow::Result<Vec<FacetCount>> {
+                let mut stmt = conn.prepare(&sql)?;
+                let counts = stmt
+                    .query_map(
+                        rusqlite::params_from_iter(params.iter().map(|p| &**p)),
+                        |row| {
+                            Ok(FacetCount {
+                                value: row.get(0)?,
+                                count: row.get(1)?,
+                            })
+                        },
+                    )?
+                    .collect::<rusqlite::Result<Vec<_>>>()?;
+                Ok(counts)
+            };
 
-            // Execute query and collect results
-            let iter = stmt.query_map(
+            // Spellings of the same tag are counted under its canonical name, and sets of post
+            // ids keep a post with two spellings from counting twice
+            let mut stmt = conn.prepare(&format!(
+                "SELECT posts.id, post_tag.value {base_query}, json_each(posts.tags) AS post_tag {where_clause}"
+            ))?;
+            let mut posts_by_tag: HashMap<String, HashSet<String>> = HashMap::new();
+            for row in stmt.query_map(
                 rusqlite::params_from_iter(params.iter().map(|p| &**p)),
-                PostService::row_to_summary_post,
-            )?;
-            let mut posts = Vec::new();
-            for post in iter {
-                posts.push(post?);
+                |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)),
+            )? {
+                let (id, tag) = row?;
+                posts_by_tag
+                    .entry(taxonomy.canonical_name(&tag))
+                    .or_default()
+                    .insert(id);
             }
+            let mut tags: Vec<FacetCount> = posts_by_tag
+                .into_iter()
+                .map(|(value, posts)| FacetCount {
+                    value,
+                    count: posts.len(),
+                })
+                .collect();
+            tags.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.value.cmp(&b.value)));
 
-            Ok::<_, anyhow::Error>((posts, usize::try_from(total)?))
+            Ok(SearchFacets {
+                tags,
+                content_types: count_facet(format!(
+                    "SELECT posts.content_type, COUNT(*) AS n {base_query} {where_clause} GROUP BY posts.content_type ORDER BY n DESC, posts.content_type"
+                ))?,
+            })
         })
         .await?
-        .context("Search execution failed")?;
+    }
+
+    pub async fn similar_to_id(
+        &self,
+        id: &str,
+        limit: usize,
+    ) -> crate::error::Result<Vec<SummaryPost>> {
+        // Any word may match, and each is quoted so nothing in the id reads as FTS5 syntax
+        let text_query = id
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|word| word.chars().count() >= 3)
+            .map(|word| format!("\"{word}\"*"))
+            .collect::<Vec<_>>()
+            .join(" OR ");
+        if text_query.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let query = SearchQuery {
+            text_query,
+            ..SearchQuery::default()
+        };
+        Ok(self.search(&query, 1, limit, None).await?.posts)
+    }
+
+    pub async fn search(
+        &self,
+        query: &SearchQuery,
+        page: usize,
+        per_page: usize,
+        after: Option<SearchCursor>,
+    ) -> Result<SearchPage, SearchError> {
+        let (mut owned_query, filters) = self.to_owned_parts(query);
+        if owned_query.text_query.is_empty()
+            && matches!(owned_query.sort, SortOrder::Relevance | SortOrder::Hybrid)
+        {
+            owned_query.sort = SortOrder::Newest;
+        }
+        let offset = page.saturating_sub(1) * per_page;
+        let pool = self.db.primary.load();
+
+        task::spawn_blocking(move || {
+            let conn = pool.get().map_err(anyhow::Error::from)?;
+
+            if !owned_query.text_query.is_empty() {
+                Self::check_syntax(&conn, &owned_query.text_query)?;
+            }
+
+            if owned_query.sort == SortOrder::Hybrid {
+                if let Some(embedding) = Self::query_embedding(&conn, &owned_query.text_query)? {
+                    return Ok(Self::hybrid_search(
+                        &conn,
+                        &owned_query,
+                        &filters,
+                        &embedding,
+                        per_page,
+                        offset,
+                        after.as_ref(),
+                    )?);
+                }
+                // Without an embedding for the query there is nothing to fuse, so fall back to
+                // plain keyword ranking.
+                owned_query.sort = SortOrder::Relevance;
+            }
+
+            Ok(Self::keyword_search(
+                &conn,
+                &owned_query,
+                &filters,
+                per_page,
+                offset,
+                after.as_ref(),
+            )?)
+        })
+        .await
+        .map_err(|e| SearchError::Database(anyhow::Error::from(e)))?
+    }
+
+    fn check_syntax(conn: &Connection, text_query: &str) -> Result<(), SearchError> {
+        match conn.query_row(
+            "SELECT rowid FROM posts_fts WHERE posts_fts MATCH ? LIMIT 1",
+            [text_query],
+            |_| Ok(()),
+        ) {
+            Ok(()) | Err(rusqlite::Error::QueryReturnedNoRows) => Ok(()),
+            // Locking, I/O and schema problems come through here too and aren't the reader's fault
+            Err(rusqlite::Error::SqliteFailure(error, Some(msg)))
+                if error.code == rusqlite::ErrorCode::Unknown
+                    && FTS5_QUERY_ERRORS
+                        .iter()
+                        .any(|prefix| msg.starts_with(prefix)) =>
+            {
+                Err(SearchError::Syntax(msg))
+            }
+            Err(e) => Err(SearchError::Database(e.into())),
+        }
+    }
+
+    fn keyword_search(
+        conn: &Connection,
+        owned_query: &SearchQuery,
+        filters: &SqlFilters,
+        per_page: usize,
+        offset: usize,
+        after: Option<&SearchCursor>,
+    ) -> anyhow::Result<SearchPage> {
+        let base_query = if owned_query.text_query.is_empty() {
+            "FROM posts".to_string()
+        } else {
+            "FROM posts INNER JOIN posts_fts ON posts.id = posts_fts.id".to_string()
+        };
+
+        // The total ignores the cursor so the page count stays the same while paging forward
+        let (conditions, count_params) = Self::build_filter_conditions(owned_query, filters, true);
+        let count_filter = if conditions.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", conditions.join(" AND "))
+        };
+        let count_query = format!("SELECT COUNT(*) {base_query} {count_filter}");
+        let total: i64 = conn.query_row(
+            &count_query,
+            rusqlite::params_from_iter(count_params.iter().map(|p| &**p)),
+            |r| r.get(0),
+        )?;
+
+        let (filter_clauses, mut params) = Self::build_search_query(owned_query, filters, after)?;
+        let (sort_expr, _) = Self::sort_column(owned_query.sort);
+        let posts_query = format!(
+            "SELECT posts.id, posts.content_type, posts.title, posts.link, posts.via, posts.quote_author, posts.date, {sort_expr} AS sort_key {base_query} {filter_clauses} LIMIT ? OFFSET ?"
+        );
+
+        let mut stmt = conn.prepare(&posts_query)?;
+        #[allow(clippy::cast_possible_wrap)]
+        params.push(Box::new(per_page as i64));
+        // A cursor already marks where the page starts
+        #[allow(clippy::cast_possible_wrap)]
+        params.push(Box::new(if after.is_some() { 0 } else { offset as i64 }));
+
+        // Execute query and collect results, remembering the sort key of the last row
+        let mut rows = stmt.query(rusqlite::params_from_iter(params.iter().map(|p| &**p)))?;
+        let mut posts = Vec::new();
+        let mut last_key = None;
+        while let Some(row) = rows.next()? {
+            posts.push(PostService::row_to_summary_post(row)?);
+            last_key = Some(match row.get::<_, Value>("sort_key")? {
+                Value::Real(score) => score.to_string(),
+                Value::Integer(n) => n.to_string(),
+                Value::Text(text) => text,
+                Value::Null | Value::Blob(_) => String::new(),
+            });
+        }
+
+        let next_cursor = match (posts.last(), last_key) {
+            (Some(post), Some(key)) if posts.len() == per_page => Some(SearchCursor {
+                key,
+                id: post.id.clone(),
+            }),
+            _ => None,
+        };
+
+        Ok(SearchPage {
+            posts,
+            total: usize::try_from(total)?,
+            next_cursor,
+        })
+    }
+
+    /// Fuses keyword and embedding rankings so posts that share no words with the query can still
+    /// surface.
+    fn hybrid_search(
+        conn: &Connection,
+        owned_query: &SearchQuery,
+        filters: &SqlFilters,
+        embedding: &[u8],
+        per_page: usize,
+        offset: usize,
+        after: Option<&SearchCursor>,
+    ) -> anyhow::Result<SearchPage> {
+        let (filter_clauses, params) = Self::build_search_query(owned_query, filters, None)?;
+        let keyword_query = format!(
+            "SELECT posts.id, bm25(posts_fts) AS sort_key FROM posts INNER JOIN posts_fts ON posts.id = posts_fts.id {filter_clauses}"
+        );
+        let mut stmt = conn.prepare(&keyword_query)?;
+        let keyword_ids = stmt
+            .query_map(
+                rusqlite::params_from_iter(params.iter().map(|p| &**p)),
+                |row| row.get(0),
+            )?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+
+        let mut stmt = conn.prepare(
+            "SELECT id FROM post_embeddings WHERE embedding MATCH ?1 AND k = ?2 ORDER BY distance",
+        )?;
+        let neighbour_ids = stmt
+            .query_map(params![embedding, VECTOR_CANDIDATES], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        let vector_ids = Self::apply_filters(conn, owned_query, filters, neighbour_ids)?;
+
+        let fused = Self::reciprocal_rank_fusion(&[keyword_ids, vector_ids]);
+        let total = fused.len();
+
+        let remaining: Vec<(String, f64)> = match after {
+            Some(cursor) => {
+                let score: f64 = cursor.key.parse().context("Invalid hybrid cursor")?;
+                fused
+                    .into_iter()
+                    .skip_while(|(id, s)| *s > score || (*s == score && *id <= cursor.id))
+                    .take(per_page)
+                    .collect()
+            }
+            None => fused.into_iter().skip(offset).take(per_page).collect(),
+        };
+
+        let next_cursor = match remaining.last() {
+            Some((id, score)) if remaining.len() == per_page => Some(SearchCursor {
+                key: score.to_string(),
+                id: id.clone(),
+            }),
+            _ => None,
+        };
+        let page_ids: Vec<String> = remaining.into_iter().map(|(id, _)| id).collect();
+
+        Ok(SearchPage {
+            posts: Self::summaries_in_order(conn, &page_ids)?,
+            total,
+            next_cursor,
+        })
+    }
+
+    fn apply_filters(
+        conn: &Connection,
+        owned_query: &SearchQuery,
+        filters: &SqlFilters,
+        ids: Vec<String>,
+    ) -> anyhow::Result<Vec<String>> {
+        if ids.is_empty() {
+            return Ok(ids);
+        }
+
+        let (mut conditions, mut params) =
+            Self::build_filter_conditions(owned_query, filters, false);
+        let placeholders = ids.iter().map(|_| "?").collect::<Vec<_>>().join(", ");
+        conditions.push(format!("posts.id IN ({placeholders})"));
+        for id in &ids {
+            params.push(Box::new(id.clone()));
+        }
+
+        let sql = format!(
+            "SELECT posts.id FROM posts WHERE {}",
+            conditions.join(" AND ")
+        );
+        let mut stmt = conn.prepare(&sql)?;
+        let allowed = stmt
+            .query_map(
+                rusqlite::params_from_iter(params.iter().map(|p| &**p)),
+                |row| row.get(0),
+            )?
+            .collect::<rusqlite::Result<HashSet<String>>>()?;
+
+        Ok(ids.into_iter().filter(|id| allowed.contains(id)).collect())
+    }
+
+    fn reciprocal_rank_fusion(rankings: &[Vec<String>]) -> Vec<(String, f64)> {
+        let mut scores: HashMap<&str, f64> = HashMap::new();
+        for ranking in rankings {
+            for (rank, id) in ranking.iter().enumerate() {
+                #[allow(clippy::cast_precision_loss)]
+                let contribution = 1.0 / (RRF_K + rank as f64 + 1.0);
+                *scores.entry(id.as_str()).or_default() += contribution;
+            }
+        }
+
+        let mut fused: Vec<(&str, f64)> = scores.into_iter().collect();
+        fused.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        fused
+            .into_iter()
+            .map(|(id, score)| (id.to_owned(), score))
+            .collect()
+    }
+
+    fn summaries_in_order(conn: &Connection, ids: &[String]) -> anyhow::Result<Vec<SummaryPost>> {
+        if ids.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let placeholders = ids.iter().map(|_| "?").collect::<Vec<_>>().join(",");
+        let sql = format!("SELECT id, content_type, title, link, via, quote_author, date FROM posts WHERE id IN ({placeholders})");
+        let mut stmt = conn.prepare(&sql)?;
+        let mut posts_map = stmt
+            .query_map(
+                rusqlite::params_from_iter(ids.iter()),
+                PostService::row_to_summary_post,
+            )?
+            .map(|post| post.map(|p| (p.id.clone(), p)))
+            .collect::<rusqlite::Result<HashMap<_, _>>>()?;
+
+        Ok(ids.iter().filter_map(|id| posts_map.remove(id)).collect())
+    }
+
+    /// Blobs use the same little-endian `f32` layout as `post_embeddings`.
+    fn query_embedding(conn: &Connection, text_query: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        if !table_exists(conn, "vocab_embeddings")? {
+            return Ok(None);
+        }
+
+        let normalized = text_query.to_lowercase();
+        let mut stmt = conn.prepare("SELECT embedding FROM vocab_embeddings WHERE term = ?")?;
+        if let Some(embedding) = stmt
+            .query_row([normalized.trim()], |row| row.get::<_, Vec<u8>>(0))
+            .optional()?
+        {
+            return Ok(Some(embedding));
+        }
+
+        let mut sum: Vec<f32> = vec![];
+        let mut count = 0u16;
+        for term in normalized
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|t| !t.is_empty())
+        {
+            let Some(blob) = stmt
+                .query_row([term], |row| row.get::<_, Vec<u8>>(0))
+                .optional()?
+            else {
+                continue;
+            };
+            let vector: Vec<f32> = blob
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect();
+
+            if sum.is_empty() {
+                sum = vector;
+            } else if sum.len() == vector.len() {
+                sum.iter_mut().zip(vector).for_each(|(s, v)| *s += v);
+            } else {
+                tracing::warn!("Skipping embedding for {term} with mismatched dimensions");
+                continue;
+            }
+            count += 1;
+        }
+
+        if count == 0 {
+            return Ok(None);
+        }
 
-        Ok((posts, total))
+        let count = f32::from(count);
+        Ok(Some(
+            sum.into_iter()
+                .flat_map(|s| (s / count).to_le_bytes())
+                .collect(),
+        ))
     }
 }
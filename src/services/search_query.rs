@@ -1,7 +1,31 @@
+use chrono::NaiveDate;
 use regex::Regex;
 
 use crate::post::ContentType;
 
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    #[default]
+    Relevance,
+    Hybrid,
+    Newest,
+    Oldest,
+    Updated,
+}
+
+impl SortOrder {
+    fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "relevance" => Some(SortOrder::Relevance),
+            "hybrid" => Some(SortOrder::Hybrid),
+            "newest" | "date" => Some(SortOrder::Newest),
+            "oldest" => Some(SortOrder::Oldest),
+            "updated" => Some(SortOrder::Updated),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct SearchQuery {
     pub text_query: String,
@@ -9,6 +33,9 @@ pub struct SearchQuery {
     pub from_date: Option<String>,
     pub to_date: Option<String>,
     pub post_type: Vec<ContentType>,
+    pub sort: SortOrder,
+    /// Filters that were recognised but couldn't be applied, fit to show the reader.
+    pub warnings: Vec<String>,
 }
 
 impl SearchQuery {
@@ -16,7 +43,9 @@ impl SearchQuery {
         let mut result = SearchQuery::default();
         let tag_re = Regex::new(r"tag:([^\s]+)").unwrap();
         let date_re = Regex::new(r"(from|to):(\d{4}-\d{2}-\d{2})").unwrap();
-        let type_re = Regex::new(r"type:(post|link|quote)").unwrap();
+        let type_re = Regex::new(r"type:(post|link|quote|note|photo|reply)").unwrap();
+        let sort_re = Regex::new(r"sort:(relevance|hybrid|newest|oldest|updated|date)").unwrap();
+        let unknown_re = Regex::new(r"\b(from|to|type|sort):([^\s]*)").unwrap();
 
         // Extract tags
         for cap in tag_re.captures_iter(raw) {
@@ -28,6 +57,14 @@ impl SearchQuery {
         // Extract dates
         for cap in date_re.captures_iter(raw) {
             if let (Some(typ), Some(date)) = (cap.get(1), cap.get(2)) {
+                if NaiveDate::parse_from_str(date.as_str(), "%Y-%m-%d").is_err() {
+                    result.warnings.push(format!(
+                        "Ignored {}:{} because it isn't a valid date",
+                        typ.as_str(),
+                        date.as_str()
+                    ));
+                    continue;
+                }
                 match typ.as_str() {
                     "from" => result.from_date = Some(date.as_str().to_string()),
                     "to" => result.to_date = Some(date.as_str().to_string()),
@@ -38,8 +75,15 @@ impl SearchQuery {
 
         // Extract type
         for cap in type_re.captures_iter(raw) {
-            if let Some(p_type) = cap.get(1).map(|m| m.as_str().to_string()) {
-                result.post_type.push(ContentType::from(p_type));
+            if let Some(Ok(p_type)) = cap.get(1).map(|m| m.as_str().parse()) {
+                result.post_type.push(p_type);
+            }
+        }
+
+        // Extract sort order, the last one wins
+        for cap in sort_re.captures_iter(raw) {
+            if let Some(sort) = cap.get(1).and_then(|m| SortOrder::from_str(m.as_str())) {
+                result.sort = sort;
             }
         }
 
@@ -47,8 +91,33 @@ impl SearchQuery {
         result.text_query = tag_re.replace_all(raw, "").to_string();
         result.text_query = date_re.replace_all(&result.text_query, "").to_string();
         result.text_query = type_re.replace_all(&result.text_query, "").to_string();
+        result.text_query = sort_re.replace_all(&result.text_query, "").to_string();
+
+        // Anything still shaped like a filter has a value we don't understand. Leaving it in the
+        // text would make FTS treat it as a column filter, so drop it and say why.
+        for cap in unknown_re.captures_iter(&result.text_query) {
+            result.warnings.push(format!(
+                "Ignored unknown {} filter \"{}\"",
+                &cap[1], &cap[2]
+            ));
+        }
+        result.text_query = unknown_re.replace_all(&result.text_query, "").to_string();
         result.text_query = result.text_query.trim().to_string();
 
         result
     }
+
+    /// The text query with every word quoted, so FTS5 matches it literally instead of reading
+    /// operators, column filters or stray quotes into it. Bare operators are dropped, since a
+    /// dangling `AND` was never meant as a word to search for.
+    pub fn literal_text(&self) -> String {
+        self.text_query
+            .split_whitespace()
+            .filter(|word| !matches!(*word, "AND" | "OR" | "NOT" | "NEAR"))
+            .map(|word| word.replace('"', ""))
+            .filter(|word| !word.is_empty())
+            .map(|word| format!("\"{word}\""))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
 }
@@ -0,0 +1,137 @@
+use super::post::{PostService, LISTED_POSTS};
+use crate::db::DbHandles;
+use crate::error::Result;
+use crate::post::SummaryPost;
+use rusqlite::{Connection, OptionalExtension};
+use serde::Serialize;
+use std::sync::Arc;
+use tokio::task;
+
+#[derive(Debug, Serialize)]
+pub struct Series {
+    pub id: String,
+    pub title: String,
+    pub description: Option<String>,
+    pub posts: Vec<SummaryPost>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SeriesPosition {
+    pub series: Series,
+    pub part: usize,
+    pub total: usize,
+    pub previous_part: Option<SummaryPost>,
+    pub next_part: Option<SummaryPost>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SeriesService {
+    db: Arc<DbHandles>,
+}
+
+impl SeriesService {
+    pub fn new(db: Arc<DbHandles>) -> Self {
+        Self { db }
+    }
+
+    fn load_series(conn: &Connection, id: &str) -> Result<Option<Series>> {
+        let Some((title, description)) = conn
+            .query_row(
+                "SELECT title, description FROM series WHERE id = ?",
+                [id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?
+        else {
+            return Ok(None);
+        };
+
+        let mut stmt = conn.prepare(&format!(
+            r"
+            SELECT posts.id, posts.content_type, posts.title, posts.link, posts.via,
+                   posts.quote_author, posts.date
+            FROM series_posts
+            INNER JOIN posts ON posts.id = series_posts.post_id
+            WHERE series_posts.series_id = ? AND posts.content_type != 'special' AND {LISTED_POSTS}
+            ORDER BY series_posts.position, posts.date
+            "
+        ))?;
+        let posts = stmt
+            .query_map([id], PostService::row_to_summary_post)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(Some(Series {
+            id: id.to_owned(),
+            title,
+            description,
+            posts,
+        }))
+    }
+
+    pub async fn get_series(&self, id: &str) -> Result<Option<Series>> {
+        let id = id.to_owned();
+        let pool = self.db.primary.load();
+
+        task::spawn_blocking(move || {
+            let conn = pool.get()?;
+            Self::load_series(&conn, &id)
+        })
+        .await?
+    }
+
+    pub async fn get_post_series(&self, post_id: &str) -> Result<Vec<SeriesPosition>> {
+        let post_id = post_id.to_owned();
+        let pool = self.db.primary.load();
+
+        task::spawn_blocking(move || {
+            let conn = pool.get()?;
+            let mut stmt = conn.prepare(
+                r"
+                SELECT series.id FROM series_posts
+                INNER JOIN series ON series.id = series_posts.series_id
+                WHERE series_posts.post_id = ?
+                ORDER BY series.title
+                ",
+            )?;
+            let ids = stmt
+                .query_map([&post_id], |row| row.get::<_, String>(0))?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+
+            let mut positions = vec![];
+            for id in ids {
+                let Some(series) = Self::load_series(&conn, &id)? else {
+                    continue;
+                };
+                let Some(index) = series.posts.iter().position(|p| p.id == post_id) else {
+                    continue;
+                };
+                let next_part =
+                    (index + 1 < series.posts.len()).then(|| series.posts[index + 1].clone());
+                let previous_part = index.checked_sub(1).map(|i| series.posts[i].clone());
+                positions.push(SeriesPosition {
+                    part: index + 1,
+                    total: series.posts.len(),
+                    series,
+                    previous_part,
+                    next_part,
+                });
+            }
+            Ok(positions)
+        })
+        .await?
+    }
+
+    pub async fn get_all_series_ids(&self) -> Result<Vec<String>> {
+        let pool = self.db.primary.load();
+
+        task::spawn_blocking(move || {
+            let conn = pool.get()?;
+            let mut stmt = conn.prepare("SELECT id FROM series ORDER BY id")?;
+            let series = stmt
+                .query_map([], |row| row.get(0))?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            Ok(series)
+        })
+        .await?
+    }
+}
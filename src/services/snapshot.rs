@@ -0,0 +1,49 @@
+use crate::db::DbHandles;
+use crate::error::Result;
+use arc_swap::{ArcSwap, Guard};
+use rusqlite::Connection;
+use std::sync::Arc;
+use tokio::task;
+
+/// Data read once from the content database and kept in memory, so the requests that use it
+/// never touch SQLite. `rebuild` replaces it from the current primary database; until that
+/// finishes, readers keep getting the previous version.
+#[derive(Debug)]
+pub struct Snapshot<T> {
+    db: Arc<DbHandles>,
+    current: ArcSwap<T>,
+}
+
+impl<T: Default + Send + Sync + 'static> Snapshot<T> {
+    pub fn new(db: Arc<DbHandles>) -> Self {
+        Self {
+            db,
+            current: ArcSwap::from_pointee(T::default()),
+        }
+    }
+
+    pub fn load(&self) -> Guard<Arc<T>> {
+        self.current.load()
+    }
+
+    pub fn load_full(&self) -> Arc<T> {
+        self.current.load_full()
+    }
+
+    /// Builds the data again with `build` and returns the new version.
+    pub async fn rebuild<F>(&self, build: F) -> Result<Arc<T>>
+    where
+        F: FnOnce(&Connection) -> Result<T> + Send + 'static,
+    {
+        let pool = self.db.primary.load_full();
+        let data = task::spawn_blocking(move || {
+            let conn = pool.get()?;
+            build(&conn)
+        })
+        .await??;
+
+        let data = Arc::new(data);
+        self.current.store(data.clone());
+        Ok(data)
+    }
+}
@@ -0,0 +1,288 @@
+use super::{post::LISTED_POSTS, snapshot::Snapshot, tag::TagTaxonomy};
+use crate::db::DbHandles;
+use crate::error::Result;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use rusqlite::Connection;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::Bound;
+use std::sync::Arc;
+use std::time::Duration;
+
+const STALE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TitleSuggestion {
+    pub id: String,
+    pub title: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TermSuggestion {
+    pub value: String,
+    pub count: usize,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Suggestions {
+    pub titles: Vec<TitleSuggestion>,
+    pub tags: Vec<TermSuggestion>,
+    pub terms: Vec<TermSuggestion>,
+}
+
+/// Keys are lowercased for case-insensitive prefix lookups.
+#[derive(Debug, Default)]
+pub struct SuggestIndex {
+    titles: Vec<(String, TitleSuggestion)>,
+    tags: BTreeMap<String, TermSuggestion>,
+    terms: BTreeMap<String, usize>,
+    /// When the next scheduled post goes live, after which the index is missing it.
+    stale_at: Option<DateTime<Utc>>,
+}
+
+impl SuggestIndex {
+    fn build(conn: &Connection, taxonomy: &TagTaxonomy) -> Result<Self> {
+        let mut index = SuggestIndex::default();
+
+        let mut stmt = conn.prepare(&format!(
+            "SELECT id, title FROM posts WHERE content_type != 'special' AND {LISTED_POSTS} AND title IS NOT NULL ORDER BY date DESC"
+        ))?;
+        let rows = stmt.query_map([], |row| {
+            Ok(TitleSuggestion {
+                id: row.get(0)?,
+                title: row.get(1)?,
+            })
+        })?;
+        for suggestion in rows {
+            let suggestion = suggestion?;
+            index
+                .titles
+                .push((suggestion.title.to_lowercase(), suggestion));
+        }
+
+        // Counted like the tags page: by canonical name, each post once
+        let mut stmt = conn.prepare(&format!(
+            "SELECT posts.id, tag.value FROM posts, json_each(posts.tags) AS tag WHERE posts.content_type != 'special' AND {LISTED_POSTS}"
+        ))?;
+        let mut posts_by_tag: HashMap<String, HashSet<String>> = HashMap::new();
+        for row in stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get(1)?)))? {
+            let (id, tag): (String, String) = row?;
+            posts_by_tag
+                .entry(taxonomy.canonical_name(&tag))
+                .or_default()
+                .insert(id);
+        }
+        for (value, posts) in posts_by_tag {
+            index.tags.insert(
+                value.to_lowercase(),
+                TermSuggestion {
+                    value,
+                    count: posts.len(),
+                },
+            );
+        }
+
+        // The content database is read-only, but the temp schema of a connection never is.
+        // The instance table lists every term by document, so unlisted posts can be left out.
+        conn.execute_batch(
+            "CREATE VIRTUAL TABLE IF NOT EXISTS temp.posts_fts_instances USING fts5vocab(main, posts_fts, instance)",
+        )?;
+        let mut stmt = conn.prepare(&format!(
+            r"
+            SELECT vocab.term, COUNT(DISTINCT vocab.doc)
+            FROM temp.posts_fts_instances AS vocab
+            INNER JOIN posts_fts ON posts_fts.rowid = vocab.doc
+            INNER JOIN posts ON posts.id = posts_fts.id
+            WHERE posts.content_type != 'special' AND {LISTED_POSTS}
+            GROUP BY vocab.term
+            "
+        ))?;
+        let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get(1)?)))?;
+        for term in rows {
+            let (term, count) = term?;
+            index.terms.insert(term, count);
+        }
+
+        let next_publish: Option<String> = conn.query_row(
+            "SELECT MIN(datetime(publish_at)) FROM post_visibility WHERE visibility = 'scheduled' AND datetime(publish_at) > datetime('now')",
+            [],
+            |row| row.get(0),
+        )?;
+        index.stale_at = next_publish
+            .and_then(|at| NaiveDateTime::parse_from_str(&at, "%Y-%m-%d %H:%M:%S").ok())
+            .map(|at| at.and_utc());
+
+        Ok(index)
+    }
+
+    fn with_prefix<'a, V>(
+        map: &'a BTreeMap<String, V>,
+        prefix: &'a str,
+    ) -> impl Iterator<Item = (&'a String, &'a V)> + 'a {
+        map.range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(move |(key, _)| key.starts_with(prefix))
+    }
+
+    /// The vocabulary term closest to `word`, preferring the more common of equally close
+    /// terms. Short words only tolerate a single typo.
+    fn closest_term(&self, word: &str) -> Option<&str> {
+        let max = if word.chars().count() <= 4 { 1 } else { 2 };
+        self.terms
+            .iter()
+            .filter_map(|(term, count)| {
+                edit_distance(word, term, max).map(|distance| (distance, *count, term))
+            })
+            .min_by(|a, b| a.0.cmp(&b.0).then_with(|| b.1.cmp(&a.1)))
+            .map(|(_, _, term)| term.as_str())
+    }
+
+    /// Filters and operators are left alone. `None` when nothing changed.
+    pub fn correct_query(&self, raw: &str) -> Option<String> {
+        let mut changed = false;
+        let corrected: Vec<String> = raw
+            .split_whitespace()
+            .map(|token| {
+                let is_word = token.chars().all(char::is_alphanumeric);
+                let is_operator = matches!(token, "AND" | "OR" | "NOT");
+                if !is_word || is_operator {
+                    return token.to_string();
+                }
+
+                let word = token.to_lowercase();
+                if self.terms.contains_key(&word) {
+                    return token.to_string();
+                }
+                match self.closest_term(&word) {
+                    Some(term) => {
+                        changed = true;
+                        term.to_string()
+                    }
+                    None => token.to_string(),
+                }
+            })
+            .collect();
+
+        changed.then(|| corrected.join(" "))
+    }
+
+    pub fn suggest(&self, prefix: &str, limit: usize) -> Suggestions {
+        let prefix = prefix.trim().to_lowercase();
+        if prefix.is_empty() {
+            return Suggestions::default();
+        }
+
+        // Titles that start with the prefix come before ones with a later word matching it
+        let (mut titles, word_matches): (Vec<_>, Vec<_>) = self
+            .titles
+            .iter()
+            .filter(|(title, _)| title.split_whitespace().any(|w| w.starts_with(&prefix)))
+            .partition(|(title, _)| title.starts_with(&prefix));
+        titles.extend(word_matches);
+
+        let mut tags: Vec<TermSuggestion> = Self::with_prefix(&self.tags, &prefix)
+            .map(|(_, tag)| tag.clone())
+            .collect();
+        tags.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.value.cmp(&b.value)));
+        tags.truncate(limit);
+
+        let mut terms: Vec<TermSuggestion> = Self::with_prefix(&self.terms, &prefix)
+            .map(|(term, count)| TermSuggestion {
+                value: term.clone(),
+                count: *count,
+            })
+            .collect();
+        terms.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.value.cmp(&b.value)));
+        terms.truncate(limit);
+
+        Suggestions {
+            titles: titles
+                .into_iter()
+                .take(limit)
+                .map(|(_, suggestion)| suggestion.clone())
+                .collect(),
+            tags,
+            terms,
+        }
+    }
+}
+
+/// Optimal string alignment distance, so swapping two adjacent letters counts as one edit.
+/// Gives up with `None` once it exceeds `max`.
+fn edit_distance(a: &str, b: &str, max: usize) -> Option<usize> {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    if a.len().abs_diff(b.len()) > max {
+        return None;
+    }
+
+    let mut before_previous = vec![0; b.len() + 1];
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+    for (i, ca) in a.iter().enumerate() {
+        current[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+            if i > 0 && j > 0 && *ca == b[j - 1] && a[i - 1] == *cb {
+                current[j + 1] = current[j + 1].min(before_previous[j - 1] + 1);
+            }
+        }
+        if current.iter().min().is_some_and(|&best| best > max) {
+            return None;
+        }
+        std::mem::swap(&mut before_previous, &mut previous);
+        std::mem::swap(&mut previous, &mut current);
+    }
+
+    Some(previous[b.len()]).filter(|&distance| distance <= max)
+}
+
+#[derive(Clone, Debug)]
+pub struct SuggestService {
+    taxonomy: Arc<Snapshot<TagTaxonomy>>,
+    index: Arc<Snapshot<SuggestIndex>>,
+}
+
+impl SuggestService {
+    pub fn new(db: Arc<DbHandles>, taxonomy: Arc<Snapshot<TagTaxonomy>>) -> Self {
+        Self {
+            taxonomy,
+            index: Arc::new(Snapshot::new(db)),
+        }
+    }
+
+    pub async fn rebuild(&self) -> Result<()> {
+        let taxonomy = self.taxonomy.load_full();
+        self.index
+            .rebuild(move |conn| SuggestIndex::build(conn, &taxonomy))
+            .await?;
+        Ok(())
+    }
+
+    /// Rebuilds the index whenever a scheduled post has gone live since the last build, so its
+    /// words show up without waiting for the next database swap. Runs until the server stops.
+    pub async fn rebuild_when_stale(&self) {
+        let mut interval = tokio::time::interval(STALE_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            if self
+                .index
+                .load()
+                .stale_at
+                .is_some_and(|stale_at| stale_at <= Utc::now())
+            {
+                if let Err(e) = self.rebuild().await {
+                    tracing::error!("Failed to rebuild suggest index: {}", e);
+                }
+            }
+        }
+    }
+
+    pub fn suggest(&self, prefix: &str, limit: usize) -> Suggestions {
+        self.index.load().suggest(prefix, limit)
+    }
+
+    pub fn correct_query(&self, raw: &str) -> Option<String> {
+        self.index.load().correct_query(raw)
+    }
+}
@@ -0,0 +1,312 @@
+use super::{
+    post::{PostService, LISTED_POSTS},
+    snapshot::Snapshot,
+};
+use crate::db::DbHandles;
+use crate::error::Result;
+use crate::post::SummaryPost;
+use anyhow::Context;
+use rusqlite::{params_from_iter, Connection, OptionalExtension};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+use tokio::task;
+
+const POSTS_PER_PAGE: i64 = 10;
+
+/// Read from the optional `tag_aliases` and `tag_hierarchy` tables. Spellings that differ only
+/// in case are always the same tag.
+#[derive(Debug, Default)]
+pub struct TagTaxonomy {
+    canonical: HashMap<String, String>,
+    parents: HashMap<String, String>,
+    spellings: HashMap<String, Vec<String>>,
+}
+
+impl TagTaxonomy {
+    fn build(conn: &Connection) -> Result<Self> {
+        let mut taxonomy = TagTaxonomy::default();
+
+        // Names the database declares win over spellings that only turn up in posts
+        let mut stmt = conn.prepare(
+            r"
+            SELECT name FROM tags
+            UNION SELECT tag FROM tag_aliases
+            UNION SELECT tag FROM tag_hierarchy
+            UNION SELECT parent FROM tag_hierarchy
+            ",
+        )?;
+        for name in stmt.query_map([], |row| row.get::<_, String>(0))? {
+            let name = name?;
+            taxonomy
+                .canonical
+                .entry(name.to_lowercase())
+                .or_insert(name);
+        }
+
+        // Otherwise the most used spelling is the canonical one
+        let mut stmt = conn.prepare(
+            "SELECT post_tag.value, COUNT(*) AS n FROM posts, json_each(posts.tags) AS post_tag GROUP BY post_tag.value ORDER BY n DESC, post_tag.value",
+        )?;
+        let used = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        for tag in &used {
+            taxonomy
+                .canonical
+                .entry(tag.to_lowercase())
+                .or_insert_with(|| tag.clone());
+        }
+
+        let mut stmt = conn.prepare("SELECT alias, tag FROM tag_aliases")?;
+        let aliases = stmt
+            .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<Vec<(String, String)>>>()?;
+        for (alias, tag) in aliases {
+            let tag = taxonomy.canonical_name(&tag);
+            taxonomy.canonical.insert(alias.to_lowercase(), tag);
+        }
+
+        let mut stmt = conn.prepare("SELECT tag, parent FROM tag_hierarchy")?;
+        for pair in stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get(1)?)))? {
+            let (tag, parent): (String, String) = pair?;
+            let (tag, parent) = (
+                taxonomy.canonical_name(&tag),
+                taxonomy.canonical_name(&parent),
+            );
+            if tag != parent {
+                taxonomy.parents.insert(tag, parent);
+            }
+        }
+
+        for tag in used {
+            taxonomy
+                .spellings
+                .entry(taxonomy.canonical_name(&tag))
+                .or_default()
+                .push(tag);
+        }
+
+        Ok(taxonomy)
+    }
+
+    pub fn canonical_name(&self, tag: &str) -> String {
+        self.canonical
+            .get(&tag.to_lowercase())
+            .cloned()
+            .unwrap_or_else(|| tag.to_owned())
+    }
+
+    pub fn normalize(&self, tags: Vec<String>) -> Vec<String> {
+        let mut seen = HashSet::new();
+        tags.into_iter()
+            .map(|tag| self.canonical_name(&tag))
+            .filter(|tag| seen.insert(tag.clone()))
+            .collect()
+    }
+
+    pub fn parent(&self, tag: &str) -> Option<&str> {
+        self.parents.get(tag).map(String::as_str)
+    }
+
+    pub fn children(&self, tag: &str) -> Vec<String> {
+        let mut children: Vec<String> = self
+            .parents
+            .iter()
+            .filter(|(_, parent)| *parent == tag)
+            .map(|(child, _)| child.clone())
+            .collect();
+        children.sort_by_key(|child| child.to_lowercase());
+        children
+    }
+
+    /// For matching against the raw `posts.tags` column.
+    pub fn expand(&self, tag: &str) -> Vec<String> {
+        let mut found = vec![self.canonical_name(tag)];
+        let mut seen: HashSet<String> = found.iter().cloned().collect();
+        let mut next = 0;
+        // A cycle in `tag_hierarchy` ends here instead of looping forever
+        while let Some(current) = found.get(next).cloned() {
+            for child in self.children(&current) {
+                if seen.insert(child.clone()) {
+                    found.push(child);
+                }
+            }
+            next += 1;
+        }
+
+        let mut values = HashSet::new();
+        for tag in found {
+            if let Some(spellings) = self.spellings.get(&tag) {
+                values.extend(spellings.iter().cloned());
+            }
+            values.insert(tag);
+        }
+        let mut values: Vec<String> = values.into_iter().collect();
+        values.sort();
+        values
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TagSummary {
+    pub name: String,
+    pub count: usize,
+    pub description: Option<String>,
+    pub parent: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TagPage {
+    pub name: String,
+    pub description: Option<String>,
+    pub parent: Option<String>,
+    pub children: Vec<String>,
+    pub posts: Vec<SummaryPost>,
+    pub total_posts: usize,
+    pub current_page: usize,
+    pub total_pages: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct TagService {
+    db: Arc<DbHandles>,
+    taxonomy: Arc<Snapshot<TagTaxonomy>>,
+}
+
+impl TagService {
+    pub fn new(db: Arc<DbHandles>) -> Self {
+        Self {
+            taxonomy: Arc::new(Snapshot::new(db.clone())),
+            db,
+        }
+    }
+
+    pub fn taxonomy(&self) -> Arc<Snapshot<TagTaxonomy>> {
+        self.taxonomy.clone()
+    }
+
+    pub async fn rebuild(&self) -> Result<()> {
+        self.taxonomy.rebuild(TagTaxonomy::build).await?;
+        Ok(())
+    }
+
+    pub fn canonical_name(&self, tag: &str) -> String {
+        self.taxonomy.load().canonical_name(tag)
+    }
+
+    pub async fn get_all_tags(&self) -> Result<Vec<TagSummary>> {
+        let pool = self.db.primary.load();
+        let taxonomy = self.taxonomy.load_full();
+
+        task::spawn_blocking(move || {
+            let conn = pool.get()?;
+            let mut stmt = conn.prepare(&format!(
+                r"
+                SELECT posts.id, post_tag.value
+                FROM posts, json_each(posts.tags) AS post_tag
+                WHERE posts.content_type != 'special' AND {LISTED_POSTS}
+                "
+            ))?;
+            // Sets of post ids, so a post using two spellings of a tag still counts once
+            let mut posts_by_tag: BTreeMap<String, HashSet<String>> = BTreeMap::new();
+            for row in stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get(1)?)))? {
+                let (id, tag): (String, String) = row?;
+                posts_by_tag
+                    .entry(taxonomy.canonical_name(&tag))
+                    .or_default()
+                    .insert(id);
+            }
+
+            let mut stmt = conn.prepare("SELECT name, description FROM tags")?;
+            let descriptions = stmt
+                .query_map([], |row| {
+                    Ok((
+                        taxonomy.canonical_name(&row.get::<_, String>(0)?),
+                        row.get::<_, Option<String>>(1)?,
+                    ))
+                })?
+                .collect::<rusqlite::Result<HashMap<_, _>>>()?;
+
+            let mut tags: Vec<TagSummary> = posts_by_tag
+                .into_iter()
+                .map(|(name, posts)| TagSummary {
+                    description: descriptions.get(&name).cloned().flatten(),
+                    parent: taxonomy.parent(&name).map(str::to_owned),
+                    count: posts.len(),
+                    name,
+                })
+                .collect();
+            tags.sort_by_key(|tag| tag.name.to_lowercase());
+            Ok(tags)
+        })
+        .await?
+    }
+
+    /// `None` when no post uses the tag and the `tags` table doesn't know it either.
+    pub async fn get_tag_page(&self, name: &str, page: usize) -> Result<Option<TagPage>> {
+        let name = name.to_owned();
+        #[allow(clippy::cast_possible_wrap)]
+        let offset = (page.saturating_sub(1) as i64) * POSTS_PER_PAGE;
+        let pool = self.db.primary.load();
+        let taxonomy = self.taxonomy.load_full();
+
+        task::spawn_blocking(move || {
+            let conn = pool.get()?;
+            let description: Option<Option<String>> = conn
+                .query_row(
+                    "SELECT description FROM tags WHERE name = ?",
+                    [&name],
+                    |row| row.get(0),
+                )
+                .optional()?;
+
+            let values = taxonomy.expand(&name);
+            let placeholders = values.iter().map(|_| "?").collect::<Vec<_>>().join(", ");
+            let tag_filter = format!(
+                "content_type != 'special' AND {LISTED_POSTS} AND EXISTS (SELECT 1 FROM json_each(posts.tags) WHERE value IN ({placeholders}))"
+            );
+
+            let total_posts: i64 = conn.query_row(
+                &format!("SELECT COUNT(*) FROM posts WHERE {tag_filter}"),
+                params_from_iter(values.iter()),
+                |row| row.get(0),
+            )?;
+
+            if total_posts == 0 && description.is_none() {
+                return Ok(None);
+            }
+
+            let mut stmt = conn.prepare(&format!(
+                "SELECT id, content_type, title, link, via, quote_author, date FROM posts WHERE {tag_filter} ORDER BY date DESC LIMIT ? OFFSET ?"
+            ))?;
+            let mut params: Vec<Box<dyn rusqlite::ToSql>> = values
+                .into_iter()
+                .map(|value| Box::new(value) as Box<dyn rusqlite::ToSql>)
+                .collect();
+            params.push(Box::new(POSTS_PER_PAGE));
+            params.push(Box::new(offset));
+            let posts = stmt
+                .query_map(
+                    params_from_iter(params.iter().map(|p| &**p)),
+                    PostService::row_to_summary_post,
+                )?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+
+            let total_pages = usize::try_from((total_posts + POSTS_PER_PAGE - 1) / POSTS_PER_PAGE)
+                .context("Total pages exceeds usize range")?;
+
+            Ok(Some(TagPage {
+                parent: taxonomy.parent(&name).map(str::to_owned),
+                children: taxonomy.children(&name),
+                name,
+                description: description.flatten(),
+                posts,
+                total_posts: usize::try_from(total_posts).context("Post count is negative")?,
+                current_page: page,
+                total_pages,
+            }))
+        })
+        .await?
+    }
+}
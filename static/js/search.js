@@ -0,0 +1,57 @@
+// Autocomplete for the search page: completes the word being typed with post titles, tags and
+// indexed terms from /api/search/suggest.
+(function () {
+  const input = document.querySelector(".search-form .search-box");
+  const list = document.getElementById("search-suggestions");
+  if (!input || !list) return;
+
+  let timer;
+  let controller;
+
+  input.addEventListener("input", () => {
+    clearTimeout(timer);
+    timer = setTimeout(update, 150);
+  });
+
+  async function update() {
+    const value = input.value;
+    const head = value.slice(0, value.lastIndexOf(" ") + 1);
+    let word = value.slice(head.length);
+    const isTag = word.startsWith("tag:");
+    if (isTag) word = word.slice("tag:".length);
+    if (word.length < 2) {
+      list.replaceChildren();
+      return;
+    }
+
+    if (controller) controller.abort();
+    controller = new AbortController();
+
+    let data;
+    try {
+      const res = await fetch(
+        `/api/search/suggest?prefix=${encodeURIComponent(word)}`,
+        { signal: controller.signal },
+      );
+      if (!res.ok) return;
+      data = await res.json();
+    } catch (e) {
+      return;
+    }
+
+    const values = [];
+    if (!isTag) {
+      data.titles.forEach((t) => values.push(head + t.title));
+      data.terms.forEach((t) => values.push(head + t.value));
+    }
+    data.tags.forEach((t) => values.push(head + "tag:" + t.value));
+
+    list.replaceChildren(
+      ...values.map((v) => {
+        const option = document.createElement("option");
+        option.value = v;
+        return option;
+      }),
+    );
+  }
+})();